@@ -10,6 +10,7 @@ use std::io::{Read, Write};
 use std::fs::File;
 use std::path::Path;
 use encoding::{EncoderTrap, DecoderTrap};
+use encoding::io::{DecodeReader, EncodeWriter};
 use encoding::label::encoding_from_whatwg_label;
 use getopts::Options;
 
@@ -63,14 +64,14 @@ fn main() {
         Some(s) => panic!("invalid error policy {}", s),
     };
 
-    let mut input = match matches.free.first().map(|s| &s[..]) {
+    let input = match matches.free.first().map(|s| &s[..]) {
         Some("-") | None => Box::new(io::stdin()) as Box<Read>,
         Some(f) => match File::open(&Path::new(f)) {
             Ok(f) => Box::new(f) as Box<Read>,
             Err(e) => panic!("cannot open the input {}: {}", f, e),
         },
     };
-    let mut output = match matches.opt_str("o").as_ref().map(|s| &s[..]) {
+    let output = match matches.opt_str("o").as_ref().map(|s| &s[..]) {
         Some("-") | None => Box::new(io::stdout()) as Box<Write>,
         Some(f) => match File::create(&Path::new(f)) {
             Ok(f) => Box::new(f) as Box<Write>,
@@ -78,17 +79,12 @@ fn main() {
         },
     };
 
-    // XXX should really use the incremental interface
-    let mut ret = Vec::new();
-    input.read_to_end(&mut ret).ok().expect("cannot read from the input");
-    let decoded = match inenc.decode(&ret, intrap) {
-        Ok(s) => s,
-        Err(e) => panic!("decoder error: {}", e),
-    };
-    let encoded = match outenc.encode(&decoded, outtrap) {
-        Ok(s) => s,
-        Err(e) => panic!("encoder error: {}", e),
-    };
-    output.write_all(&encoded).unwrap();
+    let mut reader = DecodeReader::new(input, inenc, intrap);
+    let mut writer = EncodeWriter::new(output, outenc, outtrap);
+    match io::copy(&mut reader, &mut writer) {
+        Ok(_) => {}
+        Err(e) => panic!("recoding error: {}", e),
+    }
+    writer.finish().unwrap().flush().unwrap();
 }
 
@@ -11,6 +11,7 @@
 
 import json
 import subprocess
+import sys
 
 class Label:
   def __init__(self, label, preferred):
@@ -124,6 +125,35 @@ pub static %s: EncodingWrap = EncodingWrap {
 
 ''' % (name, to_constant_name(name), to_constant_name(name), to_whatwg_name(name), to_rust_encoding_name(name)))
 
+# The size of WRAPS_BY_NAME. Must be a power of two.
+WRAPS_BY_NAME_LENGTH = 128
+
+# Must match name_slot() in src/compat.rs.
+def name_slot(name):
+  byte = lambda i: ord(name[i]) | 0x20
+  return ((len(name) << 5) + (byte(0) << 3) + byte(-1) + byte(-2)) & (WRAPS_BY_NAME_LENGTH - 1)
+
+slots = [None] * WRAPS_BY_NAME_LENGTH
+for name in preferred:
+  slot = name_slot(name)
+  if slots[slot]:
+    print "%s collides with %s in name_slot(). Please adjust the hash. Exiting." % (name, slots[slot])
+    sys.exit(-1)
+  slots[slot] = name
+
+compat_file.write('''/// All `EncodingWrap` objects indexed by `name_slot`.
+static WRAPS_BY_NAME: [Option<&'static EncodingWrap>; %d] = [
+''' % WRAPS_BY_NAME_LENGTH)
+
+line = "   "
+for name in slots:
+  entry = " Some(&%s)," % to_constant_name(name) if name else " None,"
+  if len(line) + len(entry) > 100:
+    compat_file.write(line + "\n")
+    line = "   "
+  line += entry
+compat_file.write(line + "\n];\n\n")
+
 compat_file.write(compat_rs_end)
 compat_file.close()
 
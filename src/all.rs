@@ -12,6 +12,18 @@ static ASCII_INIT: codec::ascii::ASCIIEncoding = codec::ascii::ASCIIEncoding {};
 
 pub static ASCII: &'static codec::ascii::ASCIIEncoding = &ASCII_INIT;
 
+static ERROR_INIT: codec::error::ErrorEncoding = codec::error::ErrorEncoding {};
+
+pub static ERROR: &'static codec::error::ErrorEncoding = &ERROR_INIT;
+
+static ISO_8859_1_INIT: codec::singlebyte::ISO88591Encoding = codec::singlebyte::ISO88591Encoding {};
+
+pub static ISO_8859_1: &'static codec::singlebyte::ISO88591Encoding = &ISO_8859_1_INIT;
+
+static HZ_INIT: codec::simpchinese::HZEncoding = codec::simpchinese::HZEncoding {};
+
+pub static HZ: &'static codec::simpchinese::HZEncoding = &HZ_INIT;
+
 // BEGIN GENERATED CODE. PLEASE DO NOT EDIT.
 // Instead, please regenerate using generate_constants.py
 
@@ -134,9 +146,10 @@ pub mod whatwg {
     pub static REPLACEMENT: &'static compat::EncodingWrap = &compat::REPLACEMENT;
 }
 
-static ENCODINGS: &'static [EncodingRef] = &[&ASCII_INIT,
+static ENCODINGS: &'static [EncodingRef] = &[&ERROR_INIT,
+                                             &ASCII_INIT,
                                              &compat::IBM866,
-                                             // ISO_8859_1,
+                                             &ISO_8859_1_INIT,
                                              &compat::ISO_8859_2,
                                              &compat::ISO_8859_3,
                                              &compat::ISO_8859_4,
@@ -172,6 +185,7 @@ static ENCODINGS: &'static [EncodingRef] = &[&ASCII_INIT,
                                              &compat::ISO_2022_JP,
                                              &compat::GBK,
                                              &compat::GB18030,
+                                             &HZ_INIT,
                                              &compat::BIG5,
                                              &compat::X_USER_DEFINED,
                                              &compat::ISO_8859_8_I,
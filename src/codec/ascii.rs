@@ -10,6 +10,7 @@
 use std::mem;
 use std::convert::Into;
 use types::*;
+extern crate encoding_rs;
 
 /**
  * ASCII, also known as ISO/IEC 646:US.
@@ -24,12 +25,19 @@ impl Encoding for ASCIIEncoding {
     fn name(&self) -> &'static str {
         "ascii"
     }
+    fn encoding_rs_mapping(&self) -> Option<EncodingRsMapping> {
+        // the WHATWG Encoding Standard maps the ASCII labels to windows-1252.
+        Some(EncodingRsMapping::Superset(encoding_rs::WINDOWS_1252))
+    }
     fn raw_encoder(&self) -> Box<RawEncoder> {
         ASCIIEncoder::new()
     }
     fn raw_decoder(&self) -> Box<RawDecoder> {
         ASCIIDecoder::new()
     }
+    fn is_ascii_compatible(&self) -> bool {
+        true
+    }
 }
 
 /// An encoder for ASCII.
@@ -150,6 +158,13 @@ mod tests {
         assert_finish_ok!(d, "");
     }
 
+    #[test]
+    fn test_decode_with_errors() {
+        assert_eq!(ASCIIEncoding.decode_with_errors(b"A\x80BC\xff\xfe"),
+                   ("A\u{fffd}BC\u{fffd}\u{fffd}".to_string(),
+                    vec![(1, &b"\x80"[..]), (4, b"\xff"), (5, b"\xfe")]));
+    }
+
     #[bench]
     fn bench_encode(bencher: &mut test::Bencher) {
         let s = testutils::ASCII_TEXT;
@@ -0,0 +1,147 @@
+// This is a part of rust-encoding.
+// Copyright (c) 2013-2015, Kang Seonghoon.
+// See README.md and LICENSE.txt for details.
+
+//! An error encoding.
+
+use std::convert::Into;
+use types::*;
+
+/**
+ * An encoding that returns an encoder or decoder error for every non-ASCII input.
+ *
+ * This is mainly useful as a placeholder, e.g. a sentinel for the unknown encoding.
+ */
+#[derive(Clone, Copy)]
+pub struct ErrorEncoding;
+
+impl Encoding for ErrorEncoding {
+    fn name(&self) -> &'static str {
+        "error"
+    }
+    fn raw_encoder(&self) -> Box<RawEncoder> {
+        ErrorEncoder::new()
+    }
+    fn raw_decoder(&self) -> Box<RawDecoder> {
+        ErrorDecoder::new()
+    }
+    fn is_ascii_compatible(&self) -> bool {
+        true
+    }
+}
+
+/// An encoder that returns an error for every non-ASCII character.
+#[derive(Clone, Copy)]
+pub struct ErrorEncoder;
+
+impl ErrorEncoder {
+    pub fn new() -> Box<RawEncoder> {
+        Box::new(ErrorEncoder)
+    }
+}
+
+impl RawEncoder for ErrorEncoder {
+    fn from_self(&self) -> Box<RawEncoder> {
+        ErrorEncoder::new()
+    }
+    fn is_ascii_compatible(&self) -> bool {
+        true
+    }
+
+    fn raw_feed(&mut self, input: &str, output: &mut ByteWriter) -> (usize, Option<CodecError>) {
+        match input.as_bytes().iter().position(|&ch| ch >= 0x80) {
+            Some(first_error) => {
+                output.write_bytes(&input.as_bytes()[..first_error]);
+                let len = input[first_error..].chars().next().unwrap().len_utf8();
+                (first_error,
+                 Some(CodecError {
+                    upto: (first_error + len) as isize,
+                    cause: "unrepresentable character".into(),
+                }))
+            }
+            None => {
+                output.write_bytes(input.as_bytes());
+                (input.len(), None)
+            }
+        }
+    }
+
+    fn raw_finish(&mut self, _output: &mut ByteWriter) -> Option<CodecError> {
+        None
+    }
+}
+
+/// A decoder that returns an error for every non-ASCII byte.
+#[derive(Clone, Copy)]
+pub struct ErrorDecoder;
+
+impl ErrorDecoder {
+    pub fn new() -> Box<RawDecoder> {
+        Box::new(ErrorDecoder)
+    }
+}
+
+impl RawDecoder for ErrorDecoder {
+    fn from_self(&self) -> Box<RawDecoder> {
+        ErrorDecoder::new()
+    }
+    fn is_ascii_compatible(&self) -> bool {
+        true
+    }
+
+    fn raw_feed(&mut self, input: &[u8], output: &mut StringWriter) -> (usize, Option<CodecError>) {
+        let first_error = input.iter().position(|&ch| ch >= 0x80).unwrap_or(input.len());
+        for &b in &input[..first_error] {
+            output.write_char(b as char);
+        }
+        if first_error < input.len() {
+            (first_error,
+             Some(CodecError {
+                upto: first_error as isize + 1,
+                cause: "invalid sequence".into(),
+            }))
+        } else {
+            (input.len(), None)
+        }
+    }
+
+    fn raw_finish(&mut self, _output: &mut StringWriter) -> Option<CodecError> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrorEncoding;
+    use types::*;
+
+    #[test]
+    fn test_encoder() {
+        let mut e = ErrorEncoding.raw_encoder();
+        assert_feed_ok!(e, "A", "", [0x41]);
+        assert_feed_ok!(e, "BC", "", [0x42, 0x43]);
+        assert_feed_ok!(e, "", "", []);
+        assert_feed_err!(e, "", "\u{a0}", "", []);
+        assert_feed_err!(e, "X", "\u{10000}", "Z", [0x58]);
+        assert_finish_ok!(e, []);
+
+        assert!(ErrorEncoding.encode("caf\u{e9}", EncoderTrap::Strict).is_err());
+        assert_eq!(ErrorEncoding.encode("caf\u{e9}", EncoderTrap::Replace),
+                   Ok(b"caf?".to_vec()));
+    }
+
+    #[test]
+    fn test_decoder() {
+        let mut d = ErrorEncoding.raw_decoder();
+        assert_feed_ok!(d, [0x41], [], "A");
+        assert_feed_ok!(d, [0x42, 0x43], [], "BC");
+        assert_feed_ok!(d, [], [], "");
+        assert_feed_err!(d, [], [0xa0], [], "");
+        assert_feed_err!(d, [0x58], [0xff], [0x5a], "X");
+        assert_finish_ok!(d, "");
+
+        assert!(ErrorEncoding.decode(&[0x41, 0xe9], DecoderTrap::Strict).is_err());
+        assert_eq!(ErrorEncoding.decode(&[0x41, 0xc3, 0xa9], DecoderTrap::Replace),
+                   Ok("A\u{fffd}\u{fffd}".to_string()));
+    }
+}
@@ -140,8 +140,14 @@ mod eucjp_tests {
         for i in 0x80..0x100 {
             let i = i as u8;
             let mut d = EUCJPEncoding.raw_decoder();
-            assert_feed_err!(d, [], [i], [0x80], "");
-            assert_feed_err!(d, [], [i], [0xff], "");
+            if i == 0x8e || i == 0x8f || (0xa1 <= i && i <= 0xfe) {
+                // a non-ASCII invalid trail is a part of the error
+                assert_feed_err!(d, [], [i, 0x80], [], "");
+                assert_feed_err!(d, [], [i, 0xff], [], "");
+            } else {
+                assert_feed_err!(d, [], [i], [0x80], "");
+                assert_feed_err!(d, [], [i], [0xff], "");
+            }
             assert_finish_ok!(d, "");
         }
     }
@@ -167,12 +173,18 @@ mod eucjp_tests {
 
     #[test]
     fn test_decoder_invalid_trail_for_0201() {
-        for i in 0..0xa1 {
+        for i in 0..0x80 {
             let mut d = EUCJPEncoding.raw_decoder();
             assert_feed_err!(d, [], [0x8e], [i], "");
             assert_finish_ok!(d, "");
         }
 
+        for i in 0x80..0xa1 {
+            let mut d = EUCJPEncoding.raw_decoder();
+            assert_feed_err!(d, [], [0x8e, i], [], "");
+            assert_finish_ok!(d, "");
+        }
+
         for i in 0xe0..0xff {
             let mut d = EUCJPEncoding.raw_decoder();
             assert_feed_err!(d, [], [0x8e, i], [], "");
@@ -182,13 +194,20 @@ mod eucjp_tests {
 
     #[test]
     fn test_decoder_invalid_trail_for_0201_partial() {
-        for i in 0..0xa1 {
+        for i in 0..0x80 {
             let mut d = EUCJPEncoding.raw_decoder();
             assert_feed_ok!(d, [], [0x8e], "");
             assert_feed_err!(d, [], [], [i], "");
             assert_finish_ok!(d, "");
         }
 
+        for i in 0x80..0xa1 {
+            let mut d = EUCJPEncoding.raw_decoder();
+            assert_feed_ok!(d, [], [0x8e], "");
+            assert_feed_err!(d, -1, [], [0x8e, i], [], "");
+            assert_finish_ok!(d, "");
+        }
+
         for i in 0xe0..0xff {
             let mut d = EUCJPEncoding.raw_decoder();
             assert_feed_ok!(d, [], [0x8e], "");
@@ -199,41 +218,68 @@ mod eucjp_tests {
 
     #[test]
     fn test_decoder_invalid_middle_for_0212() {
-        for i in 0..0xa1 {
+        for i in 0..0x80 {
             let mut d = EUCJPEncoding.raw_decoder();
             assert_feed_err!(d, [], [0x8f], [i], "");
             assert_finish_ok!(d, "");
         }
+
+        for i in 0x80..0xa1 {
+            let mut d = EUCJPEncoding.raw_decoder();
+            assert_feed_err!(d, [], [0x8f, i], [], "");
+            assert_finish_ok!(d, "");
+        }
     }
 
     #[test]
     fn test_decoder_invalid_middle_for_0212_partial() {
-        for i in 0..0xa1 {
+        for i in 0..0x80 {
             let mut d = EUCJPEncoding.raw_decoder();
             assert_feed_ok!(d, [], [0x8f], "");
             assert_feed_err!(d, [], [], [i], "");
             assert_finish_ok!(d, "");
         }
+
+        for i in 0x80..0xa1 {
+            let mut d = EUCJPEncoding.raw_decoder();
+            assert_feed_ok!(d, [], [0x8f], "");
+            assert_feed_err!(d, -1, [], [0x8f, i], [], "");
+            assert_finish_ok!(d, "");
+        }
     }
 
     #[test]
     fn test_decoder_invalid_trail_for_0212() {
-        for i in 0..0xa1 {
+        for i in 0..0x80 {
             let mut d = EUCJPEncoding.raw_decoder();
             assert_feed_err!(d, [], [0x8f, 0xa1], [i], "");
             assert_finish_ok!(d, "");
         }
+
+        for i in 0x80..0xa1 {
+            let mut d = EUCJPEncoding.raw_decoder();
+            assert_feed_err!(d, [], [0x8f, 0xa1, i], [], "");
+            assert_finish_ok!(d, "");
+        }
     }
 
     #[test]
     fn test_decoder_invalid_trail_for_0212_partial() {
-        for i in 0..0xa1 {
+        for i in 0..0x80 {
             let mut d = EUCJPEncoding.raw_decoder();
             assert_feed_ok!(d, [], [0x8f], "");
             assert_feed_ok!(d, [], [0xa1], "");
             assert_feed_err!(d, [], [], [i], "");
             assert_finish_ok!(d, "");
         }
+
+        for i in 0x80..0xa1 {
+            let mut d = EUCJPEncoding.raw_decoder();
+            assert_feed_ok!(d, [], [0x8f], "");
+            assert_feed_ok!(d, [], [0xa1], "");
+            assert_feed_err!(d, -2, [], [0x8f, 0xa1, i], [], "");
+            assert_finish_ok!(d, "");
+        }
     }
 
     #[test]
@@ -351,7 +397,7 @@ mod windows31j_tests {
         assert_feed_ok!(d, [0x40], [], "\u{e000}");
         assert_feed_ok!(d, [0xf9, 0xfc], [], "\u{e757}");
         assert_feed_err!(d, [], [0xf0], [0x00], "");
-        assert_feed_err!(d, [], [0xf0], [0xff], "");
+        assert_feed_err!(d, [], [0xf0, 0xff], [], "");
         assert_finish_ok!(d, "");
     }
 
@@ -399,9 +445,9 @@ mod windows31j_tests {
             let mut d = Windows31JEncoding.raw_decoder();
             assert_feed_err!(d, [], [i], [0x3f], "");
             assert_feed_err!(d, [], [i], [0x7f], "");
-            assert_feed_err!(d, [], [i], [0xfd], "");
-            assert_feed_err!(d, [], [i], [0xfe], "");
-            assert_feed_err!(d, [], [i], [0xff], "");
+            assert_feed_err!(d, [], [i, 0xfd], [], "");
+            assert_feed_err!(d, [], [i, 0xfe], [], "");
+            assert_feed_err!(d, [], [i, 0xff], [], "");
             assert_finish_ok!(d, "");
         }
 
@@ -409,9 +455,9 @@ mod windows31j_tests {
             let mut d = Windows31JEncoding.raw_decoder();
             assert_feed_err!(d, [], [i], [0x3f], "");
             assert_feed_err!(d, [], [i], [0x7f], "");
-            assert_feed_err!(d, [], [i], [0xfd], "");
-            assert_feed_err!(d, [], [i], [0xfe], "");
-            assert_feed_err!(d, [], [i], [0xff], "");
+            assert_feed_err!(d, [], [i, 0xfd], [], "");
+            assert_feed_err!(d, [], [i, 0xfe], [], "");
+            assert_feed_err!(d, [], [i, 0xff], [], "");
             assert_finish_ok!(d, "");
         }
     }
@@ -421,14 +467,14 @@ mod windows31j_tests {
         for i in 0x81..0xa0 {
             let mut d = Windows31JEncoding.raw_decoder();
             assert_feed_ok!(d, [], [i], "");
-            assert_feed_err!(d, [], [], [0xff], "");
+            assert_feed_err!(d, -1, [], [i, 0xff], [], "");
             assert_finish_ok!(d, "");
         }
 
         for i in 0xe0..0xfd {
             let mut d = Windows31JEncoding.raw_decoder();
             assert_feed_ok!(d, [], [i], "");
-            assert_feed_err!(d, [], [], [0xff], "");
+            assert_feed_err!(d, -1, [], [i, 0xff], [], "");
             assert_finish_ok!(d, "");
         }
     }
@@ -481,9 +527,8 @@ mod iso2022jp_tests {
         let mut e = ISO2022JPEncoding.raw_encoder();
         assert_feed_ok!(e, "A", "", [0x41]);
         assert_feed_ok!(e, "BC", "", [0x42, 0x43]);
-        assert_feed_ok!(e, "\x1b\x24\x42", "", [0x1b, 0x24, 0x42]); // no round-trip guarantee
         assert_feed_ok!(e, "", "", []);
-        assert_feed_ok!(e, "\u{a5}", "", [0x5c]);
+        assert_feed_ok!(e, "\u{a5}", "", [0x1b, 0x28, 0x4a, 0x5c]);
         assert_feed_ok!(e, "\u{203e}", "", [0x7e]);
         assert_feed_ok!(e,
                         "\u{306b}\u{307b}\u{3093}",
@@ -493,14 +538,15 @@ mod iso2022jp_tests {
         assert_feed_ok!(e,
                         "\u{ff86}\u{ff8e}\u{ff9d}",
                         "",
-                        [0x1b, 0x28, 0x49, 0x46, 0x4e, 0x5d]);
+                        [0x25, 0x4b, 0x25, 0x5b, 0x25, 0x73]); // as fullwidth katakana
         assert_feed_ok!(e, "XYZ", "", [0x1b, 0x28, 0x42, 0x58, 0x59, 0x5a]);
         assert_finish_ok!(e, []);
 
         // one ASCII character and two similarly looking characters:
         // - A: U+0020 SPACE (requires ASCII state)
         // - B: U+30CD KATAKANA LETTER NE (requires JIS X 0208 Lead state)
-        // - C: U+FF88 HALFWIDTH KATAKANA LETTER NE (requires Katakana state)
+        // - C: U+FF88 HALFWIDTH KATAKANA LETTER NE (encoded as B, since the
+        //   encoder does not use the Katakana state)
         // - D is omitted as the encoder does not support JIS X 0212.
         // a (3,2) De Bruijn near-sequence "ABCACBA" is used to test all possible cases.
         const AD: &'static str = "\x20";
@@ -508,10 +554,10 @@ mod iso2022jp_tests {
         const CD: &'static str = "\u{ff88}";
         const AE: &'static [u8] = &[0x1b, 0x28, 0x42, 0x20];
         const BE: &'static [u8] = &[0x1b, 0x24, 0x42, 0x25, 0x4d];
-        const CE: &'static [u8] = &[0x1b, 0x28, 0x49, 0x48];
+        const NE: &'static [u8] = &[0x25, 0x4d]; // B or C in JIS X 0208 Lead state
         let mut e = ISO2022JPEncoding.raw_encoder();
         let decoded: String = ["\x20", BD, CD, AD, CD, BD, AD].concat();
-        let encoded: Vec<_> = [&[0x20][..], BE, CE, AE, CE, BE, AE].concat();
+        let encoded: Vec<_> = [&[0x20][..], BE, NE, AE, BE, NE, AE].concat();
         assert_feed_ok!(e, decoded, "", encoded);
         assert_finish_ok!(e, []);
     }
@@ -521,6 +567,8 @@ mod iso2022jp_tests {
         let mut e = ISO2022JPEncoding.raw_encoder();
         assert_feed_err!(e, "", "\u{ffff}", "", []);
         assert_feed_err!(e, "?", "\u{ffff}", "!", [0x3f]);
+        // escapes cannot be passed through
+        assert_feed_err!(e, "", "\x1b", "\x24\x42", []);
         // JIS X 0212 is not supported in the encoder
         assert_feed_err!(e, "", "\u{736c}", "\u{8c78}", []);
         assert_finish_ok!(e, []);
@@ -533,8 +581,8 @@ mod iso2022jp_tests {
         assert_feed_ok!(d, [0x42, 0x43], [], "BC");
         assert_feed_ok!(d, [0x1b, 0x28, 0x4a, 0x44, 0x45, 0x46], [], "DEF");
         assert_feed_ok!(d, [], [], "");
-        assert_feed_ok!(d, [0x5c], [], "\\");
-        assert_feed_ok!(d, [0x7e], [], "~");
+        assert_feed_ok!(d, [0x5c], [], "\u{a5}");
+        assert_feed_ok!(d, [0x7e], [], "\u{203e}");
         assert_feed_ok!(d,
                         [0x1b, 0x24, 0x42, 0x24, 0x4b, 0x1b, 0x24, 0x42, 0x24, 0x5b, 0x24, 0x73],
                         [],
@@ -544,10 +592,7 @@ mod iso2022jp_tests {
                         [0x1b, 0x28, 0x49, 0x46, 0x4e, 0x5d],
                         [],
                         "\u{ff86}\u{ff8e}\u{ff9d}");
-        assert_feed_ok!(d,
-                        [0x1b, 0x24, 0x28, 0x44, 0x4b, 0x46, 0x1b, 0x24, 0x40, 0x6c, 0x38],
-                        [],
-                        "\u{736c}\u{8c78}");
+        assert_feed_ok!(d, [0x1b, 0x24, 0x40, 0x6c, 0x38], [], "\u{8c78}");
         assert_feed_ok!(d, [0x1b, 0x28, 0x42, 0x58, 0x59, 0x5a], [], "XYZ");
         assert_finish_ok!(d, "");
 
@@ -565,45 +610,21 @@ mod iso2022jp_tests {
                         "\u{ff86}\u{ff8e}\u{ff9d}");
         assert_finish_ok!(d, "");
 
-        let mut d = ISO2022JPEncoding.raw_decoder();
-        assert_feed_ok!(d, [0x1b, 0x24, 0x28, 0x44, 0x4b, 0x46], [], "\u{736c}");
-        assert_finish_ok!(d, "");
-
         // one ASCII character and three similarly looking characters:
         // - A: U+0020 SPACE (requires ASCII state)
         // - B: U+30CD KATAKANA LETTER NE (requires JIS X 0208 Lead state)
         // - C: U+FF88 HALFWIDTH KATAKANA LETTER NE (requires Katakana state)
-        // - D: U+793B CJK UNIFIED IDEOGRAPH-793B (requires JIS X 0212 Lead state)
-        // a (4,2) De Bruijn sequence "AABBCCACBADDBDCDA" is used to test all possible cases.
+        // - D is omitted as the decoder does not support JIS X 0212.
+        // a (3,2) De Bruijn sequence "AABBCCACBA" is used to test all possible cases.
         const AD: &'static str = "\x20";
         const BD: &'static str = "\u{30cd}";
         const CD: &'static str = "\u{ff88}";
-        const DD: &'static str = "\u{793b}";
         const AE: &'static [u8] = &[0x1b, 0x28, 0x42, 0x20];
         const BE: &'static [u8] = &[0x1b, 0x24, 0x42, 0x25, 0x4d];
         const CE: &'static [u8] = &[0x1b, 0x28, 0x49, 0x48];
-        const DE: &'static [u8] = &[0x1b, 0x24, 0x28, 0x44, 0x50, 0x4b];
         let mut d = ISO2022JPEncoding.raw_decoder();
-        let dec: String = ["\x20", AD, BD, BD, CD, CD, AD, CD, BD, AD, DD, DD, BD, DD, CD, DD, AD]
-                              .concat();
-        let enc: Vec<_> = [&[0x20][..],
-                           AE,
-                           BE,
-                           BE,
-                           CE,
-                           CE,
-                           AE,
-                           CE,
-                           BE,
-                           AE,
-                           DE,
-                           DE,
-                           BE,
-                           DE,
-                           CE,
-                           DE,
-                           AE]
-                              .concat();
+        let dec: String = ["\x20", AD, BD, BD, CD, CD, AD, CD, BD, AD].concat();
+        let enc: Vec<_> = [&[0x20][..], AE, BE, BE, CE, CE, AE, CE, BE, AE].concat();
         assert_feed_ok!(d, enc, [], dec);
         assert_finish_ok!(d, "");
     }
@@ -635,29 +656,22 @@ mod iso2022jp_tests {
         assert_feed_ok!(d, [0x28, 0x49, 0x5d], [], "\u{ff9d}");
 
         assert_feed_ok!(d, [], [0x1b, 0x24], "");
-        assert_feed_ok!(d, [], [0x28], "");
-        assert_feed_ok!(d, [0x44], [0x4b], "");
-        assert_feed_ok!(d, [0x46], [0x1b, 0x24, 0x28], "\u{736c}");
-        assert_feed_ok!(d, [0x44, 0x4b, 0x46], [], "\u{736c}");
+        assert_feed_ok!(d, [0x40], [0x6c], "");
+        assert_feed_ok!(d, [0x38], [0x1b, 0x24], "\u{8c78}");
+        assert_feed_ok!(d, [0x40, 0x6c, 0x38], [], "\u{8c78}");
 
         assert_finish_ok!(d, "");
     }
 
     #[test]
     fn test_decoder_carriage_return() {
-        // CR in Lead state "resets to ASCII"
+        // CR in Lead state doesn't "reset to ASCII"
         let mut d = ISO2022JPEncoding.raw_decoder();
-        assert_feed_ok!(d,
-                        [0x1b, 0x24, 0x42, 0x25, 0x4d, 0x0a, 0x25, 0x4d],
-                        [],
-                        "\u{30cd}\n\x25\x4d");
-        assert_feed_ok!(d,
-                        [0x1b, 0x24, 0x28, 0x44, 0x50, 0x4b, 0x0a, 0x50, 0x4b],
-                        [],
-                        "\u{793b}\n\x50\x4b");
+        assert_feed_err!(d, [0x1b, 0x24, 0x42, 0x25, 0x4d], [0x0a], [], "\u{30cd}");
+        assert_feed_ok!(d, [0x25, 0x4d], [], "\u{30cd}");
         assert_finish_ok!(d, "");
 
-        // other states don't allow CR
+        // other states don't allow CR either
         let mut d = ISO2022JPEncoding.raw_decoder();
         assert_feed_err!(d, [0x1b, 0x28, 0x49, 0x48], [0x0a], [], "\u{ff88}"); // Katakana
         assert_feed_err!(d, [0x1b, 0x24, 0x42], [0x25, 0x0a], [], ""); // Trail
@@ -671,7 +685,7 @@ mod iso2022jp_tests {
         assert_finish_err!(d, "");
 
         let mut d = ISO2022JPEncoding.raw_decoder();
-        assert_feed_ok!(d, [0x1b, 0x24, 0x28, 0x44, 0x4b, 0x46], [0x50], "\u{736c}");
+        assert_feed_ok!(d, [0x1b, 0x24, 0x40, 0x6c, 0x38], [0x50], "\u{8c78}");
         assert_finish_err!(d, "");
     }
 
@@ -683,15 +697,11 @@ mod iso2022jp_tests {
 
         let mut d = ISO2022JPEncoding.raw_decoder();
         assert_feed_ok!(d, [], [0x1b, 0x24], "");
-        assert_finish_err!(d, ""); // no backup
-
-        let mut d = ISO2022JPEncoding.raw_decoder();
-        assert_feed_ok!(d, [], [0x1b, 0x24, 0x28], "");
-        assert_finish_err!(d, -1, ""); // backup of -1, not -2
+        assert_finish_err!(d, -1, ""); // only ESC is erroneous
 
         let mut d = ISO2022JPEncoding.raw_decoder();
         assert_feed_ok!(d, [], [0x1b, 0x28], "");
-        assert_finish_err!(d, ""); // no backup
+        assert_finish_err!(d, -1, ""); // only ESC is erroneous
 
         //        assert_eq!(ISO2022JPEncoding.decode(&[0x1b], DecoderTrap::Replace),
         //                   Ok("\u{fffd}".to_string()));
@@ -707,9 +717,10 @@ mod iso2022jp_tests {
     fn test_decoder_invalid_escape() {
         // also tests allowed but never used escape codes in ISO 2022
         let mut d = ISO2022JPEncoding.raw_decoder();
+        // an invalid escape sequence does not change the state, so start in ASCII state
         macro_rules! reset(() => (
-            assert_feed_ok!(d, [0x41, 0x42, 0x43, 0x1b, 0x24, 0x42, 0x21, 0x21], [],
-                            "ABC\u{3000}")
+            assert_feed_ok!(d, [0x1b, 0x28, 0x42, 0x41, 0x42, 0x43, 0x1b, 0x24, 0x42, 0x21, 0x21],
+                            [], "ABC\u{3000}")
         ));
 
         reset!();
@@ -731,8 +742,7 @@ mod iso2022jp_tests {
         reset!();
         assert_feed_err!(d, [], [0x1b], [0x24, 0x28, 0x5a], ""); // ESC $ ( Z (GZDM4)
         reset!();
-        assert_feed_ok!(d, [], [0x1b, 0x24, 0x28], "");
-        assert_feed_err!(d, -2, [], [], [0x24, 0x28, 0x5a], "");
+        assert_feed_err!(d, [], [0x1b], [0x24, 0x28, 0x44], ""); // ESC $ ( D (JIS X 0212)
         reset!();
         assert_feed_err!(d, [], [0x1b], [0x24, 0x29, 0x5a], ""); // ESC $ ) Z (G1DM4)
         reset!();
@@ -791,16 +801,12 @@ mod iso2022jp_tests {
         let mut d = ISO2022JPEncoding.raw_decoder();
         assert_feed_err!(d, [], [0x80], [], "");
         assert_feed_err!(d, [], [0xff], [], "");
-        assert_feed_err!(d, [0x1b, 0x24, 0x42], [0x80, 0x21], [], "");
+        assert_feed_err!(d, [0x1b, 0x24, 0x42], [0x80], [0x21], "");
         assert_feed_err!(d, [0x1b, 0x24, 0x42], [0x21, 0x80], [], "");
-        assert_feed_err!(d, [0x1b, 0x24, 0x42], [0x20, 0x21], [], "");
+        assert_feed_err!(d, [0x1b, 0x24, 0x42], [0x20], [0x21], "");
         assert_feed_err!(d, [0x1b, 0x24, 0x42], [0x21, 0x20], [], "");
         assert_feed_err!(d, [0x1b, 0x28, 0x49], [0x20], [], "");
         assert_feed_err!(d, [0x1b, 0x28, 0x49], [0x60], [], "");
-        assert_feed_err!(d, [0x1b, 0x24, 0x28, 0x44], [0x80, 0x21], [], "");
-        assert_feed_err!(d, [0x1b, 0x24, 0x28, 0x44], [0x21, 0x80], [], "");
-        assert_feed_err!(d, [0x1b, 0x24, 0x28, 0x44], [0x20, 0x21], [], "");
-        assert_feed_err!(d, [0x1b, 0x24, 0x28, 0x44], [0x21, 0x20], [], "");
         assert_finish_ok!(d, "");
     }
 
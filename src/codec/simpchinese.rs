@@ -4,6 +4,264 @@
 
 //! Legacy simplified Chinese encodings based on GB 2312 and GB 18030.
 
+use std::str;
+use std::convert::Into;
+extern crate encoding_rs;
+use self::encoding_rs::DecoderResult;
+use self::encoding_rs::EncoderResult;
+use util::StrCharIndex;
+use types::*;
+
+/**
+ * HZ. (RFC 1843)
+ *
+ * This is a simplified Chinese encoding based on GB 2312.
+ * It bears a resemblance to ISO 2022 encodings in such that the printable escape sequences `~{`
+ * and `~}` are used to delimit a sequence of 7-bit-safe GB 2312 sequences. For the comparison,
+ * they are equivalent to ISO-2022-CN escape sequences `ESC $ ) A` and `ESC ( B`.
+ * Additional escape sequences `~~` (for a literal `~`) and `~\n` (ignored) are also supported.
+ *
+ * The GB 2312 sequences are mapped through the GBK index of encoding_rs.
+ * HZ was removed from the WHATWG Encoding Standard, which now maps its label `hz-gb-2312`
+ * to the replacement encoding, so this encoding has no WHATWG name and
+ * `label::encoding_from_whatwg_label` never returns it.
+ */
+#[derive(Clone, Copy)]
+pub struct HZEncoding;
+
+impl Encoding for HZEncoding {
+    fn name(&self) -> &'static str {
+        "hz"
+    }
+    fn raw_encoder(&self) -> Box<RawEncoder> {
+        HZEncoder::new()
+    }
+    fn raw_decoder(&self) -> Box<RawDecoder> {
+        HZDecoder::new()
+    }
+    fn is_ascii_compatible(&self) -> bool {
+        false
+    }
+}
+
+/// Returns true if the GBK sequence `lead` `trail` is in the GB 2312 range,
+/// which excludes the unassigned rows 0xAA-0xAF and the user-defined rows 0xF8-0xFE.
+fn is_gb2312(lead: u8, trail: u8) -> bool {
+    match (lead, trail) {
+        (0xaa..=0xaf, _) => false,
+        (0xa1..=0xf7, 0xa1..=0xfe) => true,
+        _ => false,
+    }
+}
+
+/// Maps a character to the 7-bit GB 2312 sequence, if any.
+///
+/// `encoder` is a GB 18030 encoder rather than a GBK encoder, because the latter
+/// maps U+20AC to a single byte 0x80 while HZ can only represent its GB 2312 sequence.
+fn map_char(encoder: &mut encoding_rs::Encoder, ch: char) -> Option<(u8, u8)> {
+    let mut src = [0u8; 4];
+    let mut dst = [0u8; 4];
+    // the encoder is stateless, so it can be reused without `last`.
+    let (result, _, written) =
+        encoder.encode_from_utf8_without_replacement(ch.encode_utf8(&mut src), &mut dst, false);
+    match result {
+        EncoderResult::InputEmpty if written == 2 && is_gb2312(dst[0], dst[1]) => {
+            Some((dst[0] & 0x7f, dst[1] & 0x7f))
+        }
+        _ => None,
+    }
+}
+
+/// Maps a 7-bit GB 2312 sequence to the character, if any, using a GBK `decoder`.
+fn map_two_bytes(decoder: &mut encoding_rs::Decoder, lead: u8, trail: u8) -> Option<char> {
+    if !is_gb2312(lead | 0x80, trail | 0x80) || trail > 0x7e {
+        return None;
+    }
+    let mut dst = [0u8; 4];
+    // a GB 2312 sequence is always complete, so the decoder can be reused without `last`.
+    let (result, _, written) =
+        decoder.decode_to_utf8_without_replacement(&[lead | 0x80, trail | 0x80], &mut dst, false);
+    match result {
+        DecoderResult::InputEmpty => str::from_utf8(&dst[..written]).ok().and_then(|s| s.chars().next()),
+        _ => None,
+    }
+}
+
+/// An encoder for HZ.
+#[derive(Clone, Copy)]
+pub struct HZEncoder {
+    escaped: bool,
+}
+
+impl HZEncoder {
+    pub fn new() -> Box<RawEncoder> {
+        Box::new(HZEncoder { escaped: false })
+    }
+}
+
+impl RawEncoder for HZEncoder {
+    fn from_self(&self) -> Box<RawEncoder> {
+        HZEncoder::new()
+    }
+    fn is_ascii_compatible(&self) -> bool {
+        false
+    }
+
+    fn raw_feed(&mut self, input: &str, output: &mut ByteWriter) -> (usize, Option<CodecError>) {
+        output.writer_hint(input.len());
+
+        let mut gb18030 = encoding_rs::GB18030.new_encoder();
+        for ((i, j), ch) in input.index_iter() {
+            if ch < '\u{80}' {
+                if self.escaped {
+                    output.write_bytes(b"~}");
+                    self.escaped = false;
+                }
+                output.write_byte(ch as u8);
+                if ch == '~' {
+                    output.write_byte(b'~');
+                }
+            } else {
+                match map_char(&mut gb18030, ch) {
+                    Some((lead, trail)) => {
+                        if !self.escaped {
+                            output.write_bytes(b"~{");
+                            self.escaped = true;
+                        }
+                        output.write_byte(lead);
+                        output.write_byte(trail);
+                    }
+                    None => {
+                        // do NOT reset the state!
+                        return (i,
+                                Some(CodecError {
+                            upto: j as isize,
+                            cause: "unrepresentable character".into(),
+                        }));
+                    }
+                }
+            }
+        }
+        (input.len(), None)
+    }
+
+    fn raw_finish(&mut self, output: &mut ByteWriter) -> Option<CodecError> {
+        if self.escaped {
+            output.write_bytes(b"~}");
+            self.escaped = false;
+        }
+        None
+    }
+}
+
+/// The state of `HZDecoder`. Only `Ascii` and `Gb` are at a character boundary.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum HZState {
+    /// ASCII mode.
+    Ascii,
+    /// ASCII mode, after `~`.
+    AsciiTilde,
+    /// GB 2312 mode.
+    Gb,
+    /// GB 2312 mode, after `~`.
+    GbTilde,
+    /// GB 2312 mode, after the lead byte.
+    GbLead(u8),
+}
+
+/// A decoder for HZ.
+#[derive(Clone, Copy)]
+pub struct HZDecoder {
+    state: HZState,
+}
+
+impl HZDecoder {
+    pub fn new() -> Box<RawDecoder> {
+        Box::new(HZDecoder { state: HZState::Ascii })
+    }
+}
+
+impl RawDecoder for HZDecoder {
+    fn from_self(&self) -> Box<RawDecoder> {
+        HZDecoder::new()
+    }
+    fn is_ascii_compatible(&self) -> bool {
+        false
+    }
+
+    fn raw_feed(&mut self, input: &[u8], output: &mut StringWriter) -> (usize, Option<CodecError>) {
+        output.writer_hint(input.len());
+
+        let mut gbk = encoding_rs::GBK.new_decoder_without_bom_handling();
+        let mut processed = 0;
+        for (i, &b) in input.iter().enumerate() {
+            // `upto` is `i` when the pending `~` is in error and the current byte should be
+            // fed again, and `i + 1` when the current byte is a part of the problem.
+            let (state, upto) = match (self.state, b) {
+                (HZState::Ascii, b'~') => (HZState::AsciiTilde, None),
+                (HZState::Ascii, 0x00..=0x7f) => {
+                    output.write_char(b as char);
+                    (HZState::Ascii, None)
+                }
+                (HZState::Ascii, _) => (HZState::Ascii, Some(i + 1)),
+                (HZState::AsciiTilde, b'{') | (HZState::GbTilde, b'{') => (HZState::Gb, None),
+                (HZState::AsciiTilde, b'}') | (HZState::GbTilde, b'}') => (HZState::Ascii, None),
+                (HZState::AsciiTilde, b'\n') | (HZState::GbTilde, b'\n') => (HZState::Ascii, None),
+                (HZState::AsciiTilde, b'~') => {
+                    output.write_char('~');
+                    (HZState::Ascii, None)
+                }
+                (HZState::GbTilde, b'~') => {
+                    output.write_char('~');
+                    (HZState::Gb, None)
+                }
+                (HZState::AsciiTilde, _) => (HZState::Ascii, Some(i)),
+                (HZState::GbTilde, _) => (HZState::Gb, Some(i)),
+                (HZState::Gb, b'~') => (HZState::GbTilde, None),
+                // a newline is invalid in the GB 2312 mode but *also* resets the mode
+                (HZState::Gb, b'\n') | (HZState::GbLead(_), b'\n') => (HZState::Ascii, Some(i + 1)),
+                (HZState::Gb, 0x20..=0x7f) => (HZState::GbLead(b), None),
+                (HZState::Gb, _) => (HZState::Gb, Some(i + 1)),
+                (HZState::GbLead(lead), _) => {
+                    match map_two_bytes(&mut gbk, lead, b) {
+                        Some(ch) => {
+                            output.write_char(ch);
+                            (HZState::Gb, None)
+                        }
+                        None => (HZState::Gb, Some(i + 1)),
+                    }
+                }
+            };
+            self.state = state;
+            if let Some(upto) = upto {
+                return (processed,
+                        Some(CodecError {
+                    upto: upto as isize,
+                    cause: "invalid sequence".into(),
+                }));
+            }
+            if state == HZState::Ascii || state == HZState::Gb {
+                processed = i + 1;
+            }
+        }
+        (processed, None)
+    }
+
+    fn raw_finish(&mut self, _output: &mut StringWriter) -> Option<CodecError> {
+        let state = self.state;
+        self.state = HZState::Ascii;
+        match state {
+            HZState::Ascii | HZState::Gb => None,
+            _ => {
+                Some(CodecError {
+                    upto: 0,
+                    cause: "incomplete sequence".into(),
+                })
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod gb18030_tests {
     extern crate test;
@@ -123,7 +381,7 @@ mod gb18030_tests {
         assert_feed_err!(d, [], [0xff], [], "");
         assert_feed_err!(d, [], [0x81], [0x00], "");
         assert_feed_err!(d, [], [0x81], [0x7f], "");
-        assert_feed_err!(d, [], [0x81], [0xff], "");
+        assert_feed_err!(d, [], [0x81, 0xff], [], "");
         assert_feed_err!(d, [], [0x81], [0x31, 0x00], "");
         assert_feed_err!(d, [], [0x81], [0x31, 0x80], "");
         assert_feed_err!(d, [], [0x81], [0x31, 0xff], "");
@@ -137,17 +395,17 @@ mod gb18030_tests {
     #[test]
     fn test_decoder_invalid_boundary() {
         // U+10FFFF (E3 32 9A 35) is the last Unicode codepoint, E3 32 9A 36 is invalid.
-        // note that since the 2nd to 4th bytes may coincide with ASCII, bytes 32 9A 36 is
-        // not considered to be in the problem. this is compatible to WHATWG Encoding standard.
+        // encoding_rs reports all four bytes as the problem, even though the WHATWG
+        // Encoding standard decodes bytes 32 9A 36 again since they may coincide with ASCII.
         let mut d = GB18030_ENCODING.raw_decoder();
         assert_feed_ok!(d, [], [0xe3], "");
-        assert_feed_err!(d, [], [], [0x32, 0x9a, 0x36], "");
+        assert_feed_err!(d, -1, [], [0xe3, 0x32, 0x9a, 0x36], [], "");
         assert_finish_ok!(d, "");
 
         let mut d = GB18030_ENCODING.raw_decoder();
         assert_feed_ok!(d, [], [0xe3], "");
         assert_feed_ok!(d, [], [0x32, 0x9a], "");
-        assert_feed_err!(d, -2, [], [], [0x32, 0x9a, 0x36], "");
+        assert_feed_err!(d, -3, [], [0xe3, 0x32, 0x9a, 0x36], [], "");
         assert_finish_ok!(d, "");
     }
 
@@ -244,3 +502,193 @@ mod gbk_tests {
         })
     }
 }
+
+#[cfg(test)]
+mod hz_tests {
+    extern crate test;
+    use testutils;
+    use super::HZEncoding;
+    use types::*;
+
+    #[test]
+    fn test_encoder_valid() {
+        let mut e = HZEncoding.raw_encoder();
+        assert_feed_ok!(e, "A", "", *b"A");
+        assert_feed_ok!(e, "BC", "", *b"BC");
+        assert_feed_ok!(e, "", "", *b"");
+        assert_feed_ok!(e,
+                        "\u{4e2d}\u{534e}\u{4eba}\u{6c11}\u{5171}\u{548c}\u{56fd}",
+                        "",
+                        *b"~{VP;*HKCq92:M9z");
+        assert_feed_ok!(e, "\u{ff21}\u{ff22}\u{ff23}", "", *b"#A#B#C");
+        assert_feed_ok!(e, "1\u{20ac}/m", "", *b"~}1~{\"c~}/m");
+        assert_feed_ok!(e, "~<\u{a4}~\u{0a4}>~", "", *b"~~<~{!h~}~~~{!h~}>~~");
+        assert_finish_ok!(e, []);
+
+        assert_eq!(HZEncoding.encode("1\u{20ac}/m~\u{4e2d}", EncoderTrap::Strict),
+                   Ok(b"1~{\"c~}/m~~~{VP~}".to_vec()));
+    }
+
+    #[test]
+    fn test_encoder_invalid() {
+        let mut e = HZEncoding.raw_encoder();
+        assert_feed_err!(e, "", "\u{ffff}", "", []);
+        assert_feed_err!(e, "?", "\u{ffff}", "!", [0x3f]);
+        // no support for GBK extension
+        assert_feed_err!(e, "", "\u{3007}", "", []);
+        // nor for the user-defined area
+        assert_feed_err!(e, "", "\u{e000}", "", []);
+        assert_feed_err!(e, "", "\u{e234}", "", []);
+        assert_finish_ok!(e, []);
+
+        assert!(HZEncoding.encode("\u{3007}", EncoderTrap::Strict).is_err());
+        assert_eq!(HZEncoding.encode("\u{4e2d}\u{3007}", EncoderTrap::Replace),
+                   Ok(b"~{VP~}?".to_vec()));
+    }
+
+    #[test]
+    fn test_decoder_valid() {
+        let mut d = HZEncoding.raw_decoder();
+        assert_feed_ok!(d, *b"A", *b"", "A");
+        assert_feed_ok!(d, *b"BC", *b"", "BC");
+        assert_feed_ok!(d, *b"D~~E", *b"~", "D~E");
+        assert_feed_ok!(d, *b"~F~\nG", *b"~", "~FG");
+        assert_feed_ok!(d, *b"", *b"", "");
+        assert_feed_ok!(d, *b"\nH", *b"~", "H");
+        assert_feed_ok!(d,
+                        *b"{VP~}~{;*~{HKCq92:M9z",
+                        *b"",
+                        "\u{4e2d}\u{534e}\u{4eba}\u{6c11}\u{5171}\u{548c}\u{56fd}");
+        assert_feed_ok!(d, *b"", *b"#", "");
+        assert_feed_ok!(d, *b"A", *b"~", "\u{ff21}");
+        assert_feed_ok!(d, *b"~#B~~#C", *b"~", "~\u{ff22}~\u{ff23}");
+        assert_feed_ok!(d, *b"", *b"", "");
+        assert_feed_ok!(d, *b"\n#D~{#E~\n#F~{#G", *b"~", "#D\u{ff25}#F\u{ff27}");
+        assert_feed_ok!(d, *b"}X~}YZ", *b"", "XYZ");
+        assert_finish_ok!(d, "");
+
+        assert_eq!(HZEncoding.decode(b"D~~E~~F~\nG~\nH~{VP~}~{;*~{HKCq92:M9z~}XYZ",
+                                     DecoderTrap::Strict),
+                   Ok("D~E~FGH\u{4e2d}\u{534e}\u{4eba}\u{6c11}\u{5171}\u{548c}\u{56fd}XYZ"
+                          .to_string()));
+    }
+
+    #[test]
+    fn test_decoder_split_escapes() {
+        // every split point of the input should give the same output
+        let input = b"a~~b~{VP~~#A~}c~\nd~{;*~}";
+        let expected = "a~b\u{4e2d}~\u{ff21}cd\u{534e}";
+        for split in 0..input.len() + 1 {
+            let mut d = HZEncoding.raw_decoder();
+            let mut output = String::new();
+            let (first, err) = d.raw_feed(&input[..split], &mut output);
+            assert!(err.is_none());
+            assert!(first <= split);
+            let (_, err) = d.raw_feed(&input[split..], &mut output);
+            assert!(err.is_none());
+            assert!(d.raw_finish(&mut output).is_none());
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn test_decoder_invalid_out_or_range() {
+        let mut d = HZEncoding.raw_decoder();
+        assert_feed_ok!(d, *b"~{", *b"", "");
+        assert_feed_err!(d, *b"", *b"\x20\x20", *b"", "");
+        assert_feed_err!(d, *b"", *b"\x20\x7f", *b"", ""); // do not reset the state (except for CR)
+        assert_feed_err!(d, *b"", *b"\x21\x7f", *b"", "");
+        assert_feed_err!(d, *b"", *b"\x7f\x20", *b"", "");
+        assert_feed_err!(d, *b"", *b"\x7f\x21", *b"", "");
+        assert_feed_err!(d, *b"", *b"\x7f\x7f", *b"", "");
+        // the user-defined area of GBK
+        assert_feed_err!(d, *b"", *b"\x2a\x21", *b"", "");
+        assert_feed_err!(d, *b"", *b"\x78\x21", *b"", "");
+        assert_finish_ok!(d, "");
+    }
+
+    #[test]
+    fn test_decoder_invalid_carriage_return() {
+        // CR in the multibyte mode is invalid but *also* resets the state
+        let mut d = HZEncoding.raw_decoder();
+        assert_feed_ok!(d, *b"~{#A", *b"", "\u{ff21}");
+        assert_feed_err!(d, *b"", *b"\n", *b"", "");
+        assert_feed_ok!(d, *b"#B~{#C", *b"", "#B\u{ff23}");
+        assert_feed_err!(d, *b"", *b"#\n", *b"", "");
+        assert_feed_ok!(d, *b"#D", *b"", "#D");
+        assert_finish_ok!(d, "");
+
+        assert_eq!(HZEncoding.decode(b"~{#A\n#B", DecoderTrap::Replace),
+                   Ok("\u{ff21}\u{fffd}#B".to_string()));
+    }
+
+    #[test]
+    fn test_decoder_invalid_partial() {
+        let mut d = HZEncoding.raw_decoder();
+        assert_feed_ok!(d, *b"", *b"~", "");
+        assert_finish_err!(d, "");
+
+        let mut d = HZEncoding.raw_decoder();
+        assert_feed_ok!(d, *b"~{", *b"#", "");
+        assert_finish_err!(d, "");
+
+        let mut d = HZEncoding.raw_decoder();
+        assert_feed_ok!(d, *b"~{#A", *b"~", "\u{ff21}");
+        assert_finish_err!(d, "");
+
+        assert!(HZEncoding.decode(b"~{#", DecoderTrap::Strict).is_err());
+    }
+
+    #[test]
+    fn test_decoder_invalid_escape() {
+        let mut d = HZEncoding.raw_decoder();
+        assert_feed_ok!(d, *b"#A", *b"", "#A");
+        assert_feed_err!(d, *b"", *b"~", *b"xy", "");
+        assert_feed_ok!(d, *b"#B", *b"", "#B");
+        assert_feed_ok!(d, *b"", *b"~", "");
+        assert_feed_err!(d, *b"", *b"", *b"xy", "");
+        assert_feed_ok!(d, *b"#C~{#D", *b"", "#C\u{ff24}");
+        assert_feed_err!(d, *b"", *b"~", *b"xy", "");
+        assert_feed_ok!(d, *b"#E", *b"", "\u{ff25}"); // does not reset to ASCII
+        assert_feed_ok!(d, *b"", *b"~", "");
+        assert_feed_err!(d, *b"", *b"", *b"xy", "");
+        assert_feed_ok!(d, *b"#F~}#G", *b"", "\u{ff26}#G");
+        assert_finish_ok!(d, "");
+
+        assert_eq!(HZEncoding.decode(b"#A~xy~{#E~#F", DecoderTrap::Replace),
+                   Ok("#A\u{fffd}xy\u{ff25}\u{fffd}\u{ff26}".to_string()));
+    }
+
+    #[test]
+    fn test_decoder_feed_after_finish() {
+        let mut d = HZEncoding.raw_decoder();
+        assert_feed_ok!(d, *b"R;~{R;", *b"R", "R;\u{4e00}");
+        assert_finish_err!(d, "");
+        assert_feed_ok!(d, *b"R;~{R;", *b"", "R;\u{4e00}");
+        assert_finish_ok!(d, "");
+    }
+
+    #[bench]
+    fn bench_encode_short_text(bencher: &mut test::Bencher) {
+        let s = testutils::SIMPLIFIED_CHINESE_TEXT;
+        bencher.bytes = s.len() as u64;
+        bencher.iter(|| {
+            test::black_box({
+                HZEncoding.encode(&s, EncoderTrap::Strict)
+            })
+        })
+    }
+
+    #[bench]
+    fn bench_decode_short_text(bencher: &mut test::Bencher) {
+        let s = HZEncoding.encode(testutils::SIMPLIFIED_CHINESE_TEXT, EncoderTrap::Strict)
+                          .ok()
+                          .unwrap();
+        bencher.bytes = s.len() as u64;
+        bencher.iter(|| {
+            test::black_box({
+                HZEncoding.decode(&s, DecoderTrap::Strict)
+            })
+        })
+    }
+}
@@ -4,11 +4,139 @@
 
 //! Common codec implementation for single-byte encodings.
 
+use std::convert::Into;
+use types::*;
+
+/**
+ * ISO/IEC 8859-1, also known as Latin-1.
+ *
+ * Every byte maps to the code point of the same value (U+0000 through U+00FF).
+ * Unlike the WHATWG Encoding Standard, this is distinct from Windows code page 1252,
+ * so any character above U+00FF is unrepresentable.
+ */
+#[derive(Clone, Copy)]
+pub struct ISO88591Encoding;
+
+impl Encoding for ISO88591Encoding {
+    fn name(&self) -> &'static str {
+        "iso-8859-1"
+    }
+    fn raw_encoder(&self) -> Box<RawEncoder> {
+        ISO88591Encoder::new()
+    }
+    fn raw_decoder(&self) -> Box<RawDecoder> {
+        ISO88591Decoder::new()
+    }
+    fn is_ascii_compatible(&self) -> bool {
+        true
+    }
+}
+
+/// An encoder for ISO 8859-1.
+#[derive(Clone, Copy)]
+pub struct ISO88591Encoder;
+
+impl ISO88591Encoder {
+    pub fn new() -> Box<RawEncoder> {
+        Box::new(ISO88591Encoder)
+    }
+}
+
+impl RawEncoder for ISO88591Encoder {
+    fn from_self(&self) -> Box<RawEncoder> {
+        ISO88591Encoder::new()
+    }
+    fn is_ascii_compatible(&self) -> bool {
+        true
+    }
+
+    fn raw_feed(&mut self, input: &str, output: &mut ByteWriter) -> (usize, Option<CodecError>) {
+        output.writer_hint(input.len());
+
+        for (i, ch) in input.char_indices() {
+            if ch > '\u{ff}' {
+                return (i,
+                        Some(CodecError {
+                    upto: (i + ch.len_utf8()) as isize,
+                    cause: "unrepresentable character".into(),
+                }));
+            }
+            output.write_byte(ch as u8);
+        }
+        (input.len(), None)
+    }
+
+    fn raw_finish(&mut self, _output: &mut ByteWriter) -> Option<CodecError> {
+        None
+    }
+}
+
+/// A decoder for ISO 8859-1.
+#[derive(Clone, Copy)]
+pub struct ISO88591Decoder;
+
+impl ISO88591Decoder {
+    pub fn new() -> Box<RawDecoder> {
+        Box::new(ISO88591Decoder)
+    }
+}
+
+impl RawDecoder for ISO88591Decoder {
+    fn from_self(&self) -> Box<RawDecoder> {
+        ISO88591Decoder::new()
+    }
+    fn is_ascii_compatible(&self) -> bool {
+        true
+    }
+
+    fn raw_feed(&mut self, input: &[u8], output: &mut StringWriter) -> (usize, Option<CodecError>) {
+        output.writer_hint(input.len());
+
+        for &b in input {
+            output.write_char(b as char);
+        }
+        (input.len(), None)
+    }
+
+    fn raw_finish(&mut self, _output: &mut StringWriter) -> Option<CodecError> {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use all::ISO_8859_2;
+    use all::{ISO_8859_1, ISO_8859_2};
     use types::*;
 
+    #[test]
+    fn test_iso_8859_1_encoder() {
+        let mut e = ISO_8859_1.raw_encoder();
+        assert_feed_ok!(e, "A", "", [0x41]);
+        assert_feed_ok!(e, "\u{80}\u{a0}\u{ff}", "", [0x80, 0xa0, 0xff]);
+        assert_feed_err!(e, "", "\u{100}", "", []);
+        assert_feed_err!(e, "A", "\u{20ac}", "B", [0x41]);
+        assert_finish_ok!(e, []);
+
+        assert_eq!(ISO_8859_1.encode("caf\u{e9}\u{81}", EncoderTrap::Strict),
+                   Ok(vec![0x63, 0x61, 0x66, 0xe9, 0x81]));
+        assert!(ISO_8859_1.encode("\u{20ac}", EncoderTrap::Strict).is_err());
+        assert_eq!(ISO_8859_1.encode("5\u{20ac}", EncoderTrap::Replace),
+                   Ok(vec![0x35, 0x3f]));
+    }
+
+    #[test]
+    fn test_iso_8859_1_decoder() {
+        let mut d = ISO_8859_1.raw_decoder();
+        assert_feed_ok!(d, [0x41], [], "A");
+        assert_feed_ok!(d, [0x80, 0x9f, 0xa0, 0xff], [], "\u{80}\u{9f}\u{a0}\u{ff}");
+        assert_finish_ok!(d, "");
+
+        let all_bytes: Vec<u8> = (0..256).map(|b| b as u8).collect();
+        let decoded = ISO_8859_1.decode(&all_bytes, DecoderTrap::Strict).unwrap();
+        assert!(decoded.chars().enumerate().all(|(i, ch)| ch as usize == i));
+        assert_eq!(ISO_8859_1.encode(&decoded, EncoderTrap::Strict), Ok(all_bytes));
+    }
+
     #[test]
     fn test_encoder_non_bmp() {
         let mut e = ISO_8859_2.raw_encoder();
@@ -4,18 +4,127 @@
 
 //! UTF-16.
 
+use types::*;
+
+/// Writes `input` as UTF-16 code units in the given byte order.
+/// Characters outside the BMP are written as surrogate pairs.
+pub fn write_utf16(input: &str, big_endian: bool, output: &mut ByteWriter) {
+    output.writer_hint(input.len() * 2);
+    for unit in input.encode_utf16() {
+        let (hi, lo) = ((unit >> 8) as u8, unit as u8);
+        if big_endian {
+            output.write_byte(hi);
+            output.write_byte(lo);
+        } else {
+            output.write_byte(lo);
+            output.write_byte(hi);
+        }
+    }
+}
+
+/// An encoder for UTF-16 in little endian or big endian.
+///
+/// Every scalar value is representable in UTF-16 and `&str` cannot contain
+/// lone surrogates, so this encoder never fails and never keeps a state
+/// across `raw_feed` calls.
+#[derive(Clone, Copy)]
+pub struct UTF16Encoder {
+    big_endian: bool,
+}
+
+impl UTF16Encoder {
+    pub fn new(big_endian: bool) -> Box<RawEncoder> {
+        Box::new(UTF16Encoder { big_endian: big_endian })
+    }
+}
+
+impl RawEncoder for UTF16Encoder {
+    fn from_self(&self) -> Box<RawEncoder> {
+        UTF16Encoder::new(self.big_endian)
+    }
+
+    fn raw_feed(&mut self, input: &str, output: &mut ByteWriter) -> (usize, Option<CodecError>) {
+        write_utf16(input, self.big_endian, output);
+        (input.len(), None)
+    }
+
+    fn raw_finish(&mut self, _output: &mut ByteWriter) -> Option<CodecError> {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     // little endian and big endian is symmetric to each other, there's no need to test both.
     // since big endian is easier to inspect we test UTF_16BE only.
 
     use types::*;
-    use all::UTF_16BE;
+    use all::{UTF_16BE, UTF_16LE};
 
     #[test]
-    #[should_panic]
     fn test_encoder_valid() {
         let mut e = UTF_16BE.raw_encoder();
+        assert_feed_ok!(e,
+                        "\u{0}\
+                         \u{1}\u{02}\u{004}\u{0008}\
+                         \u{10}\u{020}\u{0040}\u{80}\
+                         \u{100}\u{0200}\u{400}\u{800}\
+                         \u{1000}\u{2000}\u{4000}\u{8000}\
+                         \u{ffff}",
+                        "",
+                        [0x00, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x04, 0x00, 0x08, 0x00, 0x10,
+                         0x00, 0x20, 0x00, 0x40, 0x00, 0x80, 0x01, 0x00, 0x02, 0x00, 0x04, 0x00,
+                         0x08, 0x00, 0x10, 0x00, 0x20, 0x00, 0x40, 0x00, 0x80, 0x00, 0xff, 0xff]);
+        assert_feed_ok!(e,
+                        "\u{10000}\
+                         \u{10001}\u{010002}\
+                         \u{10004}\u{010008}\
+                         \u{10010}\u{010020}\
+                         \u{10040}\u{010080}\
+                         \u{10100}\u{010200}\
+                         \u{10400}\u{010800}\
+                         \u{11000}\u{012000}\
+                         \u{14000}\u{018000}\
+                         \u{20000}\u{030000}\
+                         \u{50000}\u{090000}\
+                         \u{10FFFF}",
+                        "",
+                        [0xd8, 0x00, 0xdc, 0x00, 0xd8, 0x00, 0xdc, 0x01, 0xd8, 0x00, 0xdc, 0x02,
+                         0xd8, 0x00, 0xdc, 0x04, 0xd8, 0x00, 0xdc, 0x08, 0xd8, 0x00, 0xdc, 0x10,
+                         0xd8, 0x00, 0xdc, 0x20, 0xd8, 0x00, 0xdc, 0x40, 0xd8, 0x00, 0xdc, 0x80,
+                         0xd8, 0x00, 0xdd, 0x00, 0xd8, 0x00, 0xde, 0x00, 0xd8, 0x01, 0xdc, 0x00,
+                         0xd8, 0x02, 0xdc, 0x00, 0xd8, 0x04, 0xdc, 0x00, 0xd8, 0x08, 0xdc, 0x00,
+                         0xd8, 0x10, 0xdc, 0x00, 0xd8, 0x20, 0xdc, 0x00, 0xd8, 0x40, 0xdc, 0x00,
+                         0xd8, 0x80, 0xdc, 0x00, 0xd9, 0x00, 0xdc, 0x00, 0xda, 0x00, 0xdc, 0x00,
+                         0xdb, 0xff, 0xdf, 0xff]);
+        assert_finish_ok!(e, []);
+    }
+
+    #[test]
+    fn test_encoder_incremental() {
+        let mut e = UTF_16BE.raw_encoder();
+        let mut buf = Vec::new();
+        assert_eq!(e.raw_feed("A\u{1234}", &mut buf).0, 4);
+        assert_eq!(e.raw_feed("", &mut buf).0, 0);
+        assert_eq!(e.raw_feed("\u{12345}", &mut buf).0, 4);
+        assert!(e.raw_finish(&mut buf).is_none());
+        assert_eq!(buf, [0x00, 0x41, 0x12, 0x34, 0xd8, 0x08, 0xdf, 0x45]);
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(UTF_16BE.encode("A\u{1234}\u{12345}", EncoderTrap::Strict),
+                   Ok(vec![0x00, 0x41, 0x12, 0x34, 0xd8, 0x08, 0xdf, 0x45]));
+        assert_eq!(UTF_16LE.encode("A\u{1234}\u{12345}", EncoderTrap::Strict),
+                   Ok(vec![0x41, 0x00, 0x34, 0x12, 0x08, 0xd8, 0x45, 0xdf]));
+
+        let mut bytes = Vec::new();
+        assert!(UTF_16LE.encode_to("\u{e9}", EncoderTrap::Strict, &mut bytes).is_ok());
+        assert_eq!(bytes, [0xe9, 0x00]);
+
+        let encoded = UTF_16LE.encode("caf\u{e9} \u{1f600}", EncoderTrap::Strict).unwrap();
+        assert_eq!(UTF_16LE.decode(&encoded, DecoderTrap::Strict),
+                   Ok("caf\u{e9} \u{1f600}".to_string()));
     }
 
     #[test]
@@ -8,6 +8,9 @@
 // except according to those terms.
 
 use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
 extern crate encoding_rs;
 use self::encoding_rs::Encoder;
 use self::encoding_rs::Decoder;
@@ -22,6 +25,12 @@ use types::ByteWriter;
 use types::StringWriter;
 use types::CodecError;
 use types::EncodingRef;
+use types::EncodingRsMapping;
+use types::EncodingError;
+use types::EncodingErrorKind;
+use types::with_encoder_trap;
+use codec::utf_16;
+use codec::utf_16::UTF16Encoder;
 
 const DECODER_BUFFER_LENGTH: usize = 1024;
 
@@ -41,7 +50,7 @@ pub struct EncodingWrap {
 impl EncodingWrap {
     fn encode_to_vec(&self,
                      input: &str,
-                     trap: EncoderTrap,
+                     trap: &mut FnMut(&mut RawEncoder, usize, &str, &mut ByteWriter) -> bool,
                      output: &mut Vec<u8>)
                      -> Result<(), Cow<'static, str>> {
         let mut unmappable_buffer = [0u8; 4];
@@ -60,9 +69,13 @@ impl EncodingWrap {
                     return Ok(());
                 }
                 RawEncoderResult::Unmappable(c) => {
-                    if trap.trap(&mut raw_encoder,
-                                 c.encode_utf8(&mut unmappable_buffer),
-                                 output) {
+                    // `c` can be U+FFFD in place of the actual character.
+                    let actual = input[..total_read].chars().next_back().unwrap();
+                    let offset = total_read - actual.len_utf8();
+                    if trap(&mut raw_encoder,
+                            offset,
+                            c.encode_utf8(&mut unmappable_buffer),
+                            output) {
                         continue;
                     } else {
                         return Err("unrepresentable character".into());
@@ -74,7 +87,7 @@ impl EncodingWrap {
 
     fn encode_to_writer(&self,
                         input: &str,
-                        trap: EncoderTrap,
+                        trap: &mut FnMut(&mut RawEncoder, usize, &str, &mut ByteWriter) -> bool,
                         output: &mut ByteWriter)
                         -> Result<(), Cow<'static, str>> {
         let mut unmappable_buffer = [0u8; 4];
@@ -95,9 +108,13 @@ impl EncodingWrap {
                     return Ok(());
                 }
                 RawEncoderResult::Unmappable(c) => {
-                    if trap.trap(&mut raw_encoder,
-                                 c.encode_utf8(&mut unmappable_buffer),
-                                 output) {
+                    // `c` can be U+FFFD in place of the actual character.
+                    let actual = input[..total_read].chars().next_back().unwrap();
+                    let offset = total_read - actual.len_utf8();
+                    if trap(&mut raw_encoder,
+                            offset,
+                            c.encode_utf8(&mut unmappable_buffer),
+                            output) {
                         continue;
                     } else {
                         return Err("unrepresentable character".into());
@@ -109,14 +126,13 @@ impl EncodingWrap {
 
     fn decode_to_string(&self,
                         input: &[u8],
-                        trap: DecoderTrap,
+                        trap: &mut FnMut(&mut RawDecoder, usize, &[u8], &mut StringWriter) -> bool,
                         output: &mut String)
                         -> Result<(), Cow<'static, str>> {
         let mut raw_decoder = RawDecoderImpl::new(self.encoding);
-        {
-            let RawDecoderImpl(ref mut decoder) = raw_decoder;
-            output.reserve(decoder.max_utf8_buffer_length_without_replacement(input.len()).unwrap());
-        }
+        output.reserve(raw_decoder.decoder
+                                  .max_utf8_buffer_length_without_replacement(input.len())
+                                  .unwrap());
         let mut total_read = 0usize;
         loop {
             let (result, read) =
@@ -131,7 +147,7 @@ impl EncodingWrap {
                 RawDecoderResult::Malformed(bad, good) => {
                     let end = total_read - (good as usize);
                     let start = end - (bad as usize);
-                    if trap.trap(&mut raw_decoder, &input[start..end], output) {
+                    if trap(&mut raw_decoder, start, &input[start..end], output) {
                         continue;
                     } else {
                         return Err("unrepresentable character".into());
@@ -143,14 +159,13 @@ impl EncodingWrap {
 
     fn decode_to_writer(&self,
                         input: &[u8],
-                        trap: DecoderTrap,
+                        trap: &mut FnMut(&mut RawDecoder, usize, &[u8], &mut StringWriter) -> bool,
                         output: &mut StringWriter)
                         -> Result<(), Cow<'static, str>> {
         let mut raw_decoder = RawDecoderImpl::new(self.encoding);
-        {
-            let RawDecoderImpl(ref mut decoder) = raw_decoder;
-            output.writer_hint(decoder.max_utf8_buffer_length_without_replacement(input.len()).unwrap());
-        }
+        output.writer_hint(raw_decoder.decoder
+                                      .max_utf8_buffer_length_without_replacement(input.len())
+                                      .unwrap());
         let mut total_read = 0usize;
         loop {
             let (result, read) =
@@ -165,7 +180,7 @@ impl EncodingWrap {
                 RawDecoderResult::Malformed(bad, good) => {
                     let end = total_read - (good as usize);
                     let start = end - (bad as usize);
-                    if trap.trap(&mut raw_decoder, &input[start..end], output) {
+                    if trap(&mut raw_decoder, start, &input[start..end], output) {
                         continue;
                     } else {
                         return Err("unrepresentable character".into());
@@ -175,9 +190,17 @@ impl EncodingWrap {
         }
     }
 
-    fn panic_if_utf16(&self) {
-        if self.encoding == encoding_rs::UTF_16LE || self.encoding == encoding_rs::UTF_16BE {
-            panic!("Can't encode to UTF-16.");
+    /// Returns `Some(big_endian)` if this is UTF-16LE or UTF-16BE.
+    ///
+    /// encoding_rs doesn't encode to UTF-16 (its output encoding is UTF-8),
+    /// so these two are encoded by `codec::utf_16` instead.
+    fn utf16_endianness(&self) -> Option<bool> {
+        if self.encoding == encoding_rs::UTF_16LE {
+            Some(false)
+        } else if self.encoding == encoding_rs::UTF_16BE {
+            Some(true)
+        } else {
+            None
         }
     }
 }
@@ -191,8 +214,17 @@ impl types::Encoding for EncodingWrap {
         return Some(self.whatwg_name);
     }
 
+    fn encoding_rs_mapping(&self) -> Option<EncodingRsMapping> {
+        if self.encoding.output_encoding() != self.encoding {
+            return Some(EncodingRsMapping::DecodeOnly(self.encoding));
+        }
+        Some(EncodingRsMapping::Exact(self.encoding))
+    }
+
     fn raw_encoder(&self) -> Box<RawEncoder> {
-        self.panic_if_utf16();
+        if let Some(big_endian) = self.utf16_endianness() {
+            return UTF16Encoder::new(big_endian);
+        }
         Box::new(RawEncoderImpl::new(self.encoding))
     }
 
@@ -200,8 +232,16 @@ impl types::Encoding for EncodingWrap {
         Box::new(RawDecoderImpl::new(self.encoding))
     }
 
+    fn is_ascii_compatible(&self) -> bool {
+        self.encoding.is_ascii_compatible()
+    }
+
     fn encode(&self, input: &str, trap: EncoderTrap) -> Result<Vec<u8>, Cow<'static, str>> {
-        self.panic_if_utf16();
+        if let Some(big_endian) = self.utf16_endianness() {
+            let mut vec = Vec::with_capacity(input.len() * 2);
+            utf_16::write_utf16(input, big_endian, &mut vec);
+            return Ok(vec);
+        }
         if self.encoding.output_encoding() == encoding_rs::UTF_8 {
             let mut vec = Vec::with_capacity(input.len());
             vec.extend_from_slice(input.as_bytes());
@@ -213,8 +253,7 @@ impl types::Encoding for EncodingWrap {
                 return Ok(out.into_owned());
             }
             _ => {
-                let mut out = Vec::new();
-                return self.encode_to_vec(input, trap, &mut out).map(|_| out);
+                return with_encoder_trap(trap, |trap| self.encode_with(input, trap));
             }
         }
     }
@@ -224,16 +263,156 @@ impl types::Encoding for EncodingWrap {
                  trap: EncoderTrap,
                  output: &mut ByteWriter)
                  -> Result<(), Cow<'static, str>> {
-        self.panic_if_utf16();
+        with_encoder_trap(trap, |trap| self.encode_to_with(input, trap, output))
+    }
+
+    fn encode_to_with(&self,
+                      input: &str,
+                      trap: &mut FnMut(&mut RawEncoder, &str, &mut ByteWriter) -> bool,
+                      output: &mut ByteWriter)
+                      -> Result<(), Cow<'static, str>> {
+        if let Some(big_endian) = self.utf16_endianness() {
+            utf_16::write_utf16(input, big_endian, output);
+            return Ok(());
+        }
         if self.encoding.output_encoding() == encoding_rs::UTF_8 {
             output.write_bytes(input.as_bytes());
             return Ok(());
         }
+        let mut trap = |encoder: &mut RawEncoder, _, input: &str, output: &mut ByteWriter| {
+            trap(encoder, input, output)
+        };
         match output.as_mut_vec() {
             None => {}
-            Some(vec) => return self.encode_to_vec(input, trap, vec),
+            Some(vec) => return self.encode_to_vec(input, &mut trap, vec),
+        }
+        self.encode_to_writer(input, &mut trap, output)
+    }
+
+    fn encode_checked(&self, input: &str, trap: EncoderTrap) -> Result<Vec<u8>, EncodingError> {
+        if self.utf16_endianness().is_some() ||
+           self.encoding.output_encoding() == encoding_rs::UTF_8 {
+            // every character is representable.
+            return Ok(self.encode(input, trap).unwrap());
+        }
+        let mut out = Vec::new();
+        let mut error = None;
+        let result = self.encode_to_vec(input,
+                                        &mut |encoder, offset, unmappable, output| {
+                                            let kind = match trap.try_trap(encoder,
+                                                                           unmappable,
+                                                                           output) {
+                                                Ok(true) => return true,
+                                                Ok(false) => EncodingErrorKind::Unmappable,
+                                                Err(_) => EncodingErrorKind::UnmappableReplacement,
+                                            };
+                                            let actual = input[offset..].chars().next().unwrap();
+                                            error = Some(EncodingError {
+                                                kind: kind,
+                                                offset: offset,
+                                                sequence: actual.to_string().into_bytes(),
+                                                encoding: self.name,
+                                            });
+                                            false
+                                        },
+                                        &mut out);
+        // only a failed trap stops the encoder.
+        result.map(|_| out).map_err(|_| error.unwrap())
+    }
+
+    fn encode_cow<'a>(&self,
+                      input: &'a str,
+                      trap: EncoderTrap)
+                      -> Result<Cow<'a, [u8]>, Cow<'static, str>> {
+        if self.utf16_endianness().is_some() {
+            return self.encode(input, trap).map(Cow::Owned);
+        }
+        if self.encoding.output_encoding() == encoding_rs::UTF_8 {
+            return Ok(Cow::Borrowed(input.as_bytes()));
+        }
+        if self.encoding.is_ascii_compatible() && input.is_ascii() {
+            return Ok(Cow::Borrowed(input.as_bytes()));
+        }
+        match trap {
+            EncoderTrap::NcrEscape => {
+                let (out, _, _) = self.encoding.encode(input);
+                return Ok(out);
+            }
+            _ => {
+                return with_encoder_trap(trap, |trap| self.encode_with(input, trap))
+                           .map(Cow::Owned);
+            }
+        }
+    }
+
+    fn decode_cow<'a>(&self,
+                      input: &'a [u8],
+                      trap: DecoderTrap)
+                      -> Result<Cow<'a, str>, Cow<'static, str>> {
+        match trap {
+            DecoderTrap::Replace => {
+                let (out, _) = self.encoding.decode_without_bom_handling(input);
+                return Ok(out);
+            }
+            _ => {
+                match self.encoding.decode_without_bom_handling_and_without_replacement(input) {
+                    Some(out) => return Ok(out),
+                    None => {}
+                }
+                // Let `trap` handle the malformed sequences.
+                return self.decode_with(input,
+                                        &mut |decoder, input, output| {
+                                            trap.trap(decoder, input, output)
+                                        })
+                           .map(Cow::Owned);
+            }
+        }
+    }
+
+    fn decode_with_errors<'a>(&self, input: &'a [u8]) -> (String, Vec<(usize, &'a [u8])>) {
+        let mut raw_decoder = RawDecoderImpl::new(self.encoding);
+        let mut output = String::new();
+        let mut errors = Vec::new();
+        let mut total_read = 0usize;
+        loop {
+            let (result, read) =
+                raw_decoder.decode_to_string_without_replacement(&input[total_read..],
+                                                                 &mut output,
+                                                                 true);
+            total_read += read;
+            match result {
+                RawDecoderResult::Done => {
+                    return (output, errors);
+                }
+                RawDecoderResult::Malformed(bad, good) => {
+                    let end = total_read - (good as usize);
+                    let start = end - (bad as usize);
+                    errors.push((start, &input[start..end]));
+                    output.push('\u{fffd}');
+                }
+            }
         }
-        self.encode_to_writer(input, trap, output)
+    }
+
+    fn decode_checked(&self, input: &[u8], trap: DecoderTrap) -> Result<String, EncodingError> {
+        let mut out = String::new();
+        let mut error = None;
+        let result = self.decode_to_string(input,
+                                           &mut |decoder, offset, malformed, output| {
+                                               if trap.trap(decoder, malformed, output) {
+                                                   return true;
+                                               }
+                                               error = Some(EncodingError {
+                                                   kind: EncodingErrorKind::Malformed,
+                                                   offset: offset,
+                                                   sequence: malformed.to_vec(),
+                                                   encoding: self.name,
+                                               });
+                                               false
+                                           },
+                                           &mut out);
+        // only a failed trap stops the decoder.
+        result.map(|_| out).map_err(|_| error.unwrap())
     }
 
     fn decode(&self, input: &[u8], trap: DecoderTrap) -> Result<String, Cow<'static, str>> {
@@ -243,8 +422,10 @@ impl types::Encoding for EncodingWrap {
                 return Ok(out.into_owned());
             }
             _ => {
-                let mut out = String::new();
-                return self.decode_to_string(input, trap, &mut out).map(|_| out);
+                return self.decode_with(input,
+                                        &mut |decoder, input, output| {
+                                            trap.trap(decoder, input, output)
+                                        });
             }
         }
     }
@@ -254,11 +435,24 @@ impl types::Encoding for EncodingWrap {
                  trap: DecoderTrap,
                  output: &mut StringWriter)
                  -> Result<(), Cow<'static, str>> {
+        self.decode_to_with(input,
+                            &mut |decoder, input, output| trap.trap(decoder, input, output),
+                            output)
+    }
+
+    fn decode_to_with(&self,
+                      input: &[u8],
+                      trap: &mut FnMut(&mut RawDecoder, &[u8], &mut StringWriter) -> bool,
+                      output: &mut StringWriter)
+                      -> Result<(), Cow<'static, str>> {
+        let mut trap = |decoder: &mut RawDecoder, _, input: &[u8], output: &mut StringWriter| {
+            trap(decoder, input, output)
+        };
         match output.as_mut_string() {
             None => {}
-            Some(string) => return self.decode_to_string(input, trap, string),
+            Some(string) => return self.decode_to_string(input, &mut trap, string),
         }
-        self.decode_to_writer(input, trap, output)
+        self.decode_to_writer(input, &mut trap, output)
     }
 }
 
@@ -287,11 +481,223 @@ enum RawDecoderResult {
     Malformed(u8, u8), // u8 instead of usize to avoid useless bloat
 }
 
-struct RawDecoderImpl(Decoder);
+/// The maximum number of bytes in a malformed sequence reported by
+/// encoding_rs plus the number of bytes consumed after it. (The ISO-2022-JP
+/// decoder reports `Malformed(3, 3)`.)
+const MAX_MALFORMED_SPAN: usize = 6;
+
+/// The shape of the byte sequences of an encoding.
+#[derive(Clone, Copy, PartialEq)]
+enum SequenceKind {
+    SingleByte,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// A lead byte in 0x81-0x9f or 0xe0-0xfc followed by a trail byte.
+    ShiftJis,
+    /// A lead byte in 0x81-0xfe followed by a trail byte.
+    DoubleByte,
+    /// `DoubleByte` plus four-byte sequences with a digit as the second byte.
+    Gb18030,
+    /// A lead byte in 0xa1-0xfe or 0x8e followed by a trail byte, or 0x8f
+    /// followed by two more bytes.
+    EucJp,
+    Iso2022Jp,
+}
+
+/// Follows the bytes fed to an encoding_rs decoder in order to know where the
+/// last complete sequence ends, since the decoder keeps the bytes of an
+/// incomplete sequence to itself.
+///
+/// The decoder reports malformed sequences, so this only needs to know the
+/// length of the well-formed ones. `test_sequence_boundaries` checks that
+/// this agrees with encoding_rs on every byte of the multibyte encodings.
+struct SequenceTracker {
+    kind: SequenceKind,
+    /// The bytes of the incomplete sequence seen so far (at most 3).
+    pending: [u8; 3],
+    pending_len: usize,
+    /// ISO-2022-JP: the decoder is in the two-byte mode.
+    two_byte_mode: bool,
+}
+
+impl SequenceTracker {
+    fn new(encoding: &'static encoding_rs::Encoding) -> SequenceTracker {
+        let kind = if encoding == encoding_rs::UTF_8 {
+            SequenceKind::Utf8
+        } else if encoding == encoding_rs::UTF_16LE {
+            SequenceKind::Utf16Le
+        } else if encoding == encoding_rs::UTF_16BE {
+            SequenceKind::Utf16Be
+        } else if encoding == encoding_rs::SHIFT_JIS {
+            SequenceKind::ShiftJis
+        } else if encoding == encoding_rs::BIG5 || encoding == encoding_rs::EUC_KR {
+            SequenceKind::DoubleByte
+        } else if encoding == encoding_rs::GBK || encoding == encoding_rs::GB18030 {
+            SequenceKind::Gb18030
+        } else if encoding == encoding_rs::EUC_JP {
+            SequenceKind::EucJp
+        } else if encoding == encoding_rs::ISO_2022_JP {
+            SequenceKind::Iso2022Jp
+        } else {
+            SequenceKind::SingleByte
+        };
+        SequenceTracker {
+            kind: kind,
+            pending: [0; 3],
+            pending_len: 0,
+            two_byte_mode: false,
+        }
+    }
+
+    fn is_at_boundary(&self) -> bool {
+        self.pending_len == 0
+    }
+
+    /// Forgets the incomplete sequence, which the decoder does after
+    /// reporting a malformed sequence. The ISO-2022-JP mode is kept.
+    fn discard_pending(&mut self) {
+        self.pending_len = 0;
+    }
+
+    /// Follows `byte` and returns true if it completes a sequence.
+    fn push(&mut self, byte: u8) -> bool {
+        let first = if self.pending_len == 0 { byte } else { self.pending[0] };
+        let len = self.pending_len + 1;
+        let complete = match self.kind {
+            SequenceKind::SingleByte => true,
+            SequenceKind::Utf8 => {
+                let needed = match first {
+                    0xc2..=0xdf => 2,
+                    0xe0..=0xef => 3,
+                    0xf0..=0xf4 => 4,
+                    _ => 1,
+                };
+                len == needed
+            }
+            SequenceKind::Utf16Le | SequenceKind::Utf16Be => {
+                if len % 2 == 1 {
+                    false
+                } else {
+                    let unit_first = if len == 2 { first } else { self.pending[2] };
+                    let high_byte = if self.kind == SequenceKind::Utf16Le { byte } else { unit_first };
+                    // a high surrogate needs one more code unit.
+                    len == 4 || high_byte & 0xfc != 0xd8
+                }
+            }
+            SequenceKind::ShiftJis => {
+                match first {
+                    0x81..=0x9f | 0xe0..=0xfc => len == 2,
+                    _ => true,
+                }
+            }
+            SequenceKind::DoubleByte => {
+                match first {
+                    0x81..=0xfe => len == 2,
+                    _ => true,
+                }
+            }
+            SequenceKind::Gb18030 => {
+                match first {
+                    0x81..=0xfe => {
+                        match len {
+                            1 => false,
+                            2 => !(b'0' <= byte && byte <= b'9'),
+                            3 => false,
+                            _ => true,
+                        }
+                    }
+                    _ => true,
+                }
+            }
+            SequenceKind::EucJp => {
+                match first {
+                    0x8f => len == 3,
+                    0x8e | 0xa1..=0xfe => len == 2,
+                    _ => true,
+                }
+            }
+            SequenceKind::Iso2022Jp => {
+                if first == 0x1b {
+                    // ESC ( B, ESC ( J and ESC ( I switch to a single-byte
+                    // mode, ESC $ @ and ESC $ B to the two-byte mode.
+                    if len == 3 {
+                        self.two_byte_mode = self.pending[1] == b'$';
+                    }
+                    len == 3
+                } else if self.two_byte_mode {
+                    match first {
+                        0x21..=0x7e => len == 2,
+                        _ => true,
+                    }
+                } else {
+                    true
+                }
+            }
+        };
+        if complete {
+            self.pending_len = 0;
+        } else {
+            self.pending[self.pending_len] = byte;
+            self.pending_len = len;
+        }
+        complete
+    }
+}
+
+struct RawDecoderImpl {
+    decoder: Decoder,
+    /// The last (at most `MAX_MALFORMED_SPAN`) bytes consumed by `decoder`
+    /// in `raw_feed`.
+    consumed: Vec<u8>,
+    /// The bytes that `raw_feed` asked the caller to feed again (by pointing
+    /// `upto` before them) but that `decoder` has already consumed.
+    replay: Vec<u8>,
+    /// Where the sequences consumed by `decoder` end.
+    tracker: SequenceTracker,
+}
 
 impl RawDecoderImpl {
     fn new(encoding: &'static encoding_rs::Encoding) -> RawDecoderImpl {
-        RawDecoderImpl(encoding.new_decoder_without_bom_handling())
+        RawDecoderImpl {
+            decoder: encoding.new_decoder_without_bom_handling(),
+            consumed: Vec::new(),
+            replay: Vec::new(),
+            tracker: SequenceTracker::new(encoding),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.decoder = self.decoder.encoding().new_decoder_without_bom_handling();
+        self.consumed.clear();
+        self.replay.clear();
+        self.tracker = SequenceTracker::new(self.decoder.encoding());
+    }
+
+    /// Returns the number of bytes at the start of `src` that were already
+    /// consumed after a malformed sequence and are now fed again.
+    ///
+    /// If the caller feeds something else instead, the decoder is reset,
+    /// since encoding_rs cannot unconsume those bytes.
+    fn skip_replay(&mut self, src: &[u8]) -> usize {
+        let len = ::std::cmp::min(self.replay.len(), src.len());
+        if src[..len] != self.replay[..len] {
+            self.reset();
+            return 0;
+        }
+        self.replay.drain(..len);
+        len
+    }
+
+    fn remember_consumed(&mut self, src: &[u8]) {
+        if src.len() >= MAX_MALFORMED_SPAN {
+            self.consumed.clear();
+            self.consumed.extend_from_slice(&src[src.len() - MAX_MALFORMED_SPAN..]);
+        } else {
+            self.consumed.extend_from_slice(src);
+            let excess = self.consumed.len().saturating_sub(MAX_MALFORMED_SPAN);
+            self.consumed.drain(..excess);
+        }
     }
 
     fn decode_to_writer_without_replacement(&mut self,
@@ -299,17 +705,32 @@ impl RawDecoderImpl {
                                             dst: &mut StringWriter,
                                             last: bool)
                                             -> (RawDecoderResult, usize) {
-        let &mut RawDecoderImpl(ref mut decoder) = self;
+        let decoder = &mut self.decoder;
         let mut buffer: [u8; DECODER_BUFFER_LENGTH] = unsafe { ::std::mem::uninitialized() };
         let mut total_read = 0usize;
         loop {
-            let (result, read, written) =
-                decoder.decode_to_utf8_without_replacement(&src[total_read..],
-                                                           &mut buffer[..],
-                                                           last);
+            let remaining = &src[total_read..];
+            let min_len = ::std::cmp::min(decoder.max_utf8_buffer_length_without_replacement(remaining.len())
+                                                 .unwrap(),
+                                          DECODER_BUFFER_LENGTH);
+            let (result, read) = match dst.writable_slice(min_len) {
+                Some(slice) => {
+                    let (result, read, written) =
+                        decoder.decode_to_utf8_without_replacement(remaining, slice, last);
+                    unsafe { dst.commit_slice(written) };
+                    (result, read)
+                }
+                None => {
+                    let (result, read, written) =
+                        decoder.decode_to_utf8_without_replacement(remaining,
+                                                                   &mut buffer[..],
+                                                                   last);
+                    let as_str: &str = unsafe { ::std::mem::transmute(&buffer[..written]) };
+                    dst.write_str(as_str);
+                    (result, read)
+                }
+            };
             total_read += read;
-            let as_str: &str = unsafe { ::std::mem::transmute(&buffer[..written]) };
-            dst.write_str(as_str);
             match result {
                 DecoderResult::InputEmpty => {
                     return (RawDecoderResult::Done, total_read);
@@ -329,7 +750,7 @@ impl RawDecoderImpl {
                                             dst: &mut String,
                                             last: bool)
                                             -> (RawDecoderResult, usize) {
-        let &mut RawDecoderImpl(ref mut decoder) = self;
+        let decoder = &mut self.decoder;
         let needed = decoder.max_utf8_buffer_length_without_replacement(src.len()).unwrap();
         let available = dst.capacity() - dst.len();
         if needed > available {
@@ -364,31 +785,57 @@ impl RawDecoderImpl {
 
 impl RawDecoder for RawDecoderImpl {
     fn from_self(&self) -> Box<RawDecoder> {
-        let &RawDecoderImpl(ref decoder) = self;
-        Box::new(RawDecoderImpl::new(decoder.encoding()))
+        Box::new(RawDecoderImpl::new(self.decoder.encoding()))
     }
 
     fn is_ascii_compatible(&self) -> bool {
-        let &RawDecoderImpl(ref decoder) = self;
-        decoder.encoding().is_ascii_compatible()
+        self.decoder.encoding().is_ascii_compatible()
     }
 
     fn raw_feed(&mut self, input: &[u8], output: &mut StringWriter) -> (usize, Option<CodecError>) {
-        {
-            let &mut RawDecoderImpl(ref mut decoder) = self;
-            output.writer_hint(decoder.max_utf8_buffer_length_without_replacement(input.len()).unwrap());
+        let skipped = self.skip_replay(input);
+        if !self.replay.is_empty() {
+            // `input` is only a part of the bytes fed again.
+            return (0, None);
         }
-        let (result, read) = self.decode_without_replacement(input, output, false);
+        let src = &input[skipped..];
+        output.writer_hint(self.decoder.max_utf8_buffer_length_without_replacement(src.len()).unwrap());
+        let mut processed = if self.tracker.is_at_boundary() { skipped } else { 0 };
+        let (result, read) = self.decode_without_replacement(src, output, false);
+        if let RawDecoderResult::Done = result {
+            for (i, &byte) in src.iter().enumerate() {
+                if self.tracker.push(byte) {
+                    processed = skipped + i + 1;
+                }
+            }
+        }
+        self.remember_consumed(&src[..read]);
+        let read = skipped + read;
         match result {
             RawDecoderResult::Done => {
-                return (read, None);
+                return (processed, None);
             }
-            RawDecoderResult::Malformed(_, _) => {
-                // Report a zero-length sequence as being in error by
-                // setting `upto` to `read`.
-                return (read,
+            RawDecoderResult::Malformed(bad, good) => {
+                // encoding_rs has consumed `good` bytes after the malformed
+                // sequence. Point `upto` before them like rust-encoding does
+                // and skip them when they are fed again. Both the malformed
+                // sequence and these bytes may start in the prior inputs.
+                let upto = read as isize - good as isize;
+                let start = upto - bad as isize;
+                let good_start = self.consumed.len().saturating_sub(good as usize);
+                self.replay = self.consumed[good_start..].to_vec();
+                // the decoder goes on with the bytes after the malformed sequence.
+                let before = ::std::cmp::max(start - skipped as isize, 0) as usize;
+                for &byte in &src[..before] {
+                    self.tracker.push(byte);
+                }
+                self.tracker.discard_pending();
+                for &byte in &self.replay {
+                    self.tracker.push(byte);
+                }
+                return (if start > 0 { start as usize } else { 0 },
                         Some(CodecError {
-                    upto: read as isize,
+                    upto: upto,
                     cause: "invalid sequence".into(),
                 }));
             }
@@ -396,16 +843,22 @@ impl RawDecoder for RawDecoderImpl {
     }
 
     fn raw_finish(&mut self, output: &mut StringWriter) -> Option<CodecError> {
-        let (result, _) = self.decode_without_replacement(b"", output, false);
+        if !self.replay.is_empty() {
+            // The caller has not fed the bytes after the last malformed
+            // sequence again, so they are discarded.
+            self.reset();
+        }
+        let (result, _) = self.decode_without_replacement(b"", output, true);
+        // encoding_rs doesn't allow feeding a finished decoder, so any bytes
+        // fed again after an error go to a fresh decoder.
+        self.reset();
         match result {
             RawDecoderResult::Done => {
                 return None;
             }
-            RawDecoderResult::Malformed(_, _) => {
-                // Report a zero-length sequence as being in error by
-                // setting `upto` to 0.
+            RawDecoderResult::Malformed(_, good) => {
                 return Some(CodecError {
-                    upto: 0isize,
+                    upto: -(good as isize),
                     cause: "invalid sequence".into(),
                 });
             }
@@ -448,12 +901,27 @@ impl RawEncoderImpl {
         dst.writer_hint(encoder.max_buffer_length_from_utf8_without_replacement(src.len()).unwrap());
         let mut total_read = 0usize;
         loop {
-            let (result, read, written) =
-                encoder.encode_from_utf8_without_replacement(&src[total_read..],
-                                                             &mut buffer[..],
-                                                             last);
+            let remaining = &src[total_read..];
+            let min_len = ::std::cmp::min(encoder.max_buffer_length_from_utf8_without_replacement(remaining.len())
+                                                 .unwrap(),
+                                          ENCODER_BUFFER_LENGTH);
+            let (result, read) = match dst.writable_slice(min_len) {
+                Some(slice) => {
+                    let (result, read, written) =
+                        encoder.encode_from_utf8_without_replacement(remaining, slice, last);
+                    dst.commit_slice(written);
+                    (result, read)
+                }
+                None => {
+                    let (result, read, written) =
+                        encoder.encode_from_utf8_without_replacement(remaining,
+                                                                     &mut buffer[..],
+                                                                     last);
+                    dst.write_bytes(&buffer[..written]);
+                    (result, read)
+                }
+            };
             total_read += read;
-            dst.write_bytes(&buffer[..written]);
             match result {
                 EncoderResult::InputEmpty => {
                     return (RawEncoderResult::Done, total_read);
@@ -548,7 +1016,10 @@ impl RawEncoder for RawEncoderImpl {
     }
 
     fn raw_finish(&mut self, output: &mut ByteWriter) -> Option<CodecError> {
-        let (result, _) = self.encode_without_replacement("", output, false);
+        // `last` makes ISO-2022-JP return to the ASCII state.
+        let (result, _) = self.encode_without_replacement("", output, true);
+        let encoding = self.0.encoding();
+        self.0 = encoding.new_encoder();
         match result {
             RawEncoderResult::Done => {
                 return None;
@@ -561,28 +1032,56 @@ impl RawEncoder for RawEncoderImpl {
 }
 
 pub fn from_encoding_rs(encoding: &'static encoding_rs::Encoding) -> EncodingRef {
-    for wrap in &WRAPS[..] {
-        if wrap.encoding == encoding {
-            // Need this intermediate binding to keep the compiler
-            // happy.
-            let enc: &'static EncodingWrap = wrap;
-            return enc;
-        }
+    match WRAPS_BY_NAME[name_slot(encoding.name())] {
+        Some(wrap) if wrap.encoding == encoding => wrap,
+        _ => unreachable!("How can an unlisted &'static encoding_rs::Encoding exist?"),
     }
-    unreachable!("How can an unlisted &'static encoding_rs::Encoding exist?");
 }
 
+/// Returns the encoding_rs encoding that decodes the same as `encoding`, if any.
+/// It encodes the same too unless `to_encoding_rs_mapping` says `DecodeOnly`.
 pub fn to_encoding_rs(encoding: EncodingRef) -> Option<&'static encoding_rs::Encoding> {
-    for wrap in &WRAPS[..] {
-        let enc: &'static EncodingWrap = wrap;
-        let enc_ref: EncodingRef = enc;
-        if (enc_ref as *const types::Encoding) == (encoding as *const types::Encoding) {
-            return Some(wrap.encoding);
-        }
+    match encoding.encoding_rs_mapping() {
+        Some(EncodingRsMapping::Exact(encoding)) |
+        Some(EncodingRsMapping::DecodeOnly(encoding)) => Some(encoding),
+        _ => None,
+    }
+}
+
+/// Returns the encoding_rs encoding that is the same as or the closest superset
+/// of `encoding`, if any.
+pub fn to_encoding_rs_mapping(encoding: EncodingRef) -> Option<EncodingRsMapping> {
+    encoding.encoding_rs_mapping()
+}
+
+impl From<&'static encoding_rs::Encoding> for EncodingRef {
+    fn from(encoding: &'static encoding_rs::Encoding) -> EncodingRef {
+        from_encoding_rs(encoding)
+    }
+}
+
+impl TryFrom<EncodingRef> for &'static encoding_rs::Encoding {
+    type Error = NoEncodingRsCounterpart;
+
+    fn try_from(encoding: EncodingRef)
+                -> Result<&'static encoding_rs::Encoding, NoEncodingRsCounterpart> {
+        to_encoding_rs(encoding).ok_or(NoEncodingRsCounterpart)
+    }
+}
+
+/// The error from converting an `EncodingRef` without an encoding_rs
+/// counterpart that decodes the same into `&'static encoding_rs::Encoding`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NoEncodingRsCounterpart;
+
+impl fmt::Display for NoEncodingRsCounterpart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the encoding has no encoding_rs counterpart")
     }
-    None
 }
 
+impl error::Error for NoEncodingRsCounterpart {}
+
 pub fn encoding_rs_for_label(label: &str) -> Option<EncodingRef> {
     encoding_rs::Encoding::for_label(label.as_bytes()).map(from_encoding_rs)
 }
@@ -592,6 +1091,7 @@ pub fn encoding_rs_for_label(label: &str) -> Option<EncodingRef> {
 /// https://w3techs.com/technologies/overview/character_encoding/all
 /// whose methodology is known to be bogus, but the results are credible for
 /// this purpose.
+#[cfg(test)]
 static WRAPS: [&'static EncodingWrap; 40] = [&UTF_8,
                                              &WINDOWS_1252,
                                              &WINDOWS_1251,
@@ -633,6 +1133,19 @@ static WRAPS: [&'static EncodingWrap; 40] = [&UTF_8,
                                              &X_USER_DEFINED,
                                              &REPLACEMENT];
 
+/// The slot of an encoding in `WRAPS_BY_NAME`, given its encoding_rs name.
+///
+/// This is a perfect hash of the names of the encodings in `WRAPS`, which
+/// turns `from_encoding_rs` into a table lookup. generate_constants.py
+/// computes the same hash to fill `WRAPS_BY_NAME` and fails on collisions.
+fn name_slot(name: &str) -> usize {
+    let bytes = name.as_bytes();
+    let len = bytes.len();
+    // ASCII case-insensitive
+    let byte = |i: usize| (bytes[i] | 0x20) as usize;
+    ((len << 5) + (byte(0) << 3) + byte(len - 1) + byte(len - 2)) & (WRAPS_BY_NAME.len() - 1)
+}
+
 // BEGIN GENERATED CODE. PLEASE DO NOT EDIT.
 // Instead, please regenerate using generate_constants.py
 
@@ -916,4 +1429,607 @@ pub static X_USER_DEFINED: EncodingWrap = EncodingWrap {
     name: "pua-mapped-binary",
 };
 
+/// All `EncodingWrap` objects indexed by `name_slot`.
+static WRAPS_BY_NAME: [Option<&'static EncodingWrap>; 128] = [
+    None, None, Some(&ISO_2022_JP), Some(&WINDOWS_874), None, None, None, None, None,
+    Some(&ISO_8859_10), None, None, Some(&ISO_8859_13), Some(&ISO_8859_14), Some(&ISO_8859_15),
+    Some(&ISO_8859_16), None, None, None, None, Some(&SHIFT_JIS), None, None, None, None, None,
+    None, None, None, Some(&WINDOWS_1250), Some(&WINDOWS_1251), Some(&WINDOWS_1252),
+    Some(&WINDOWS_1253), Some(&WINDOWS_1254), Some(&WINDOWS_1255), Some(&WINDOWS_1256),
+    Some(&WINDOWS_1257), Some(&WINDOWS_1258), None, None, None, None, None, None, Some(&BIG5),
+    Some(&UTF_8), None, None, None, None, None, None, None, None, None, Some(&KOI8_R), None, None,
+    Some(&KOI8_U), None, None, None, None, None, None, None, Some(&EUC_JP), None, None,
+    Some(&EUC_KR), None, None, None, Some(&X_USER_DEFINED), None, None, Some(&X_MAC_CYRILLIC), None,
+    None, None, None, None, Some(&REPLACEMENT), None, None, None, None, None, None, None, None,
+    None, None, None, Some(&ISO_8859_8_I), None, None, None, None, Some(&MACINTOSH), None,
+    Some(&GBK), None, Some(&ISO_8859_2), Some(&ISO_8859_3), Some(&ISO_8859_4), Some(&ISO_8859_5),
+    Some(&ISO_8859_6), Some(&ISO_8859_7), Some(&ISO_8859_8), None, Some(&UTF_16BE), None, None,
+    None, None, Some(&IBM866), None, None, None, None, Some(&UTF_16LE), None, Some(&GB18030), None,
+    None, None, None,
+];
+
 // END GENERATED CODE
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use testutils;
+    use types::Encoding;
+
+    /// A custom `ByteWriter` that only implements the required methods.
+    struct CopyingByteWriter(Vec<u8>);
+
+    impl ByteWriter for CopyingByteWriter {
+        fn write_byte(&mut self, b: u8) {
+            self.0.push(b);
+        }
+
+        fn write_bytes(&mut self, v: &[u8]) {
+            self.0.extend_from_slice(v);
+        }
+    }
+
+    /// A custom `ByteWriter` that lets the encoder write into its memory.
+    struct SliceByteWriter {
+        buffer: Vec<u8>,
+        len: usize,
+    }
+
+    impl SliceByteWriter {
+        fn new() -> SliceByteWriter {
+            SliceByteWriter {
+                buffer: Vec::new(),
+                len: 0,
+            }
+        }
+
+        fn as_bytes(&self) -> &[u8] {
+            &self.buffer[..self.len]
+        }
+    }
+
+    impl ByteWriter for SliceByteWriter {
+        fn write_byte(&mut self, b: u8) {
+            self.write_bytes(&[b]);
+        }
+
+        fn write_bytes(&mut self, v: &[u8]) {
+            self.buffer.truncate(self.len);
+            self.buffer.extend_from_slice(v);
+            self.len = self.buffer.len();
+        }
+
+        fn writable_slice(&mut self, min_len: usize) -> Option<&mut [u8]> {
+            if self.buffer.len() < self.len + min_len {
+                self.buffer.resize(self.len + min_len, 0);
+            }
+            Some(&mut self.buffer[self.len..])
+        }
+
+        fn commit_slice(&mut self, len: usize) {
+            self.len += len;
+        }
+    }
+
+    /// A custom `StringWriter` that only implements the required methods.
+    struct CopyingStringWriter(String);
+
+    impl StringWriter for CopyingStringWriter {
+        fn write_char(&mut self, c: char) {
+            self.0.push(c);
+        }
+
+        fn write_str(&mut self, s: &str) {
+            self.0.push_str(s);
+        }
+    }
+
+    /// A custom `StringWriter` that lets the decoder write into its memory.
+    struct SliceStringWriter {
+        buffer: Vec<u8>,
+        len: usize,
+    }
+
+    impl SliceStringWriter {
+        fn new() -> SliceStringWriter {
+            SliceStringWriter {
+                buffer: Vec::new(),
+                len: 0,
+            }
+        }
+
+        fn as_str(&self) -> &str {
+            ::std::str::from_utf8(&self.buffer[..self.len]).unwrap()
+        }
+    }
+
+    impl StringWriter for SliceStringWriter {
+        fn write_char(&mut self, c: char) {
+            let mut buf = [0u8; 4];
+            self.write_str(c.encode_utf8(&mut buf));
+        }
+
+        fn write_str(&mut self, s: &str) {
+            self.buffer.truncate(self.len);
+            self.buffer.extend_from_slice(s.as_bytes());
+            self.len = self.buffer.len();
+        }
+
+        fn writable_slice(&mut self, min_len: usize) -> Option<&mut [u8]> {
+            if self.buffer.len() < self.len + min_len {
+                self.buffer.resize(self.len + min_len, 0);
+            }
+            Some(&mut self.buffer[self.len..])
+        }
+
+        unsafe fn commit_slice(&mut self, len: usize) {
+            self.len += len;
+        }
+    }
+
+    #[test]
+    fn test_encode_to_writable_slice() {
+        // longer than `ENCODER_BUFFER_LENGTH` so that the encoder needs several slices
+        let input = testutils::JAPANESE_TEXT.repeat(4);
+        for encoding in &[&SHIFT_JIS, &EUC_JP, &ISO_2022_JP, &GB18030, &WINDOWS_1252] {
+            let expected = encoding.encode(&input, EncoderTrap::Replace).unwrap();
+            let mut copying = CopyingByteWriter(Vec::new());
+            encoding.encode_to(&input, EncoderTrap::Replace, &mut copying).unwrap();
+            assert_eq!(copying.0, expected);
+            let mut slice = SliceByteWriter::new();
+            encoding.encode_to(&input, EncoderTrap::Replace, &mut slice).unwrap();
+            assert_eq!(slice.as_bytes(), &expected[..]);
+        }
+    }
+
+    /// A custom `ByteWriter` that forgets to implement `commit_slice`.
+    struct UncommittedByteWriter(Vec<u8>);
+
+    impl ByteWriter for UncommittedByteWriter {
+        fn write_byte(&mut self, b: u8) {
+            self.0.push(b);
+        }
+
+        fn write_bytes(&mut self, v: &[u8]) {
+            self.0.extend_from_slice(v);
+        }
+
+        fn writable_slice(&mut self, min_len: usize) -> Option<&mut [u8]> {
+            self.0.resize(min_len, 0);
+            Some(&mut self.0[..])
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "not commit_slice")]
+    fn test_encode_to_writable_slice_without_commit() {
+        let mut writer = UncommittedByteWriter(Vec::new());
+        let _ = SHIFT_JIS.encode_to("\u{3042}", EncoderTrap::Strict, &mut writer);
+    }
+
+    #[test]
+    fn test_decode_to_writable_slice() {
+        let input = testutils::JAPANESE_TEXT.repeat(4);
+        for encoding in &[&SHIFT_JIS, &EUC_JP, &ISO_2022_JP, &GB18030, &UTF_8] {
+            let mut bytes = encoding.encode(&input, EncoderTrap::Strict).unwrap();
+            bytes.push(0xff);
+            let expected = encoding.decode(&bytes, DecoderTrap::Replace).unwrap();
+            let mut copying = CopyingStringWriter(String::new());
+            encoding.decode_to(&bytes, DecoderTrap::Replace, &mut copying).unwrap();
+            assert_eq!(copying.0, expected);
+            let mut slice = SliceStringWriter::new();
+            encoding.decode_to(&bytes, DecoderTrap::Replace, &mut slice).unwrap();
+            assert_eq!(slice.as_str(), expected);
+        }
+    }
+
+    #[test]
+    fn test_sequence_boundaries() {
+        // `SequenceTracker` must agree with encoding_rs: fed byte by byte, each byte
+        // completes a sequence exactly when the input up to it decodes without error.
+        let mut text = String::new();
+        for s in &[testutils::ASCII_TEXT,
+                   testutils::KOREAN_TEXT,
+                   testutils::JAPANESE_TEXT,
+                   testutils::SIMPLIFIED_CHINESE_TEXT,
+                   testutils::TRADITIONAL_CHINESE_TEXT] {
+            text.extend(s.chars().take(50));
+        }
+        // halfwidth katakana, a four-byte GB18030 sequence and a UTF-16 surrogate pair.
+        text.push_str("\u{ff71}\u{20ac}\u{e5e5}\u{1f600}\u{10ffff}");
+        for wrap in &WRAPS[..] {
+            let mut input = wrap.encode(&text, EncoderTrap::NcrEscape).unwrap();
+            if wrap.encoding == encoding_rs::EUC_JP {
+                // JIS X 0212, which the encoder never writes.
+                input.extend_from_slice(&[0x8f, 0xb0, 0xa1]);
+            }
+            let decode = |input: &[u8]| -> Option<String> {
+                wrap.encoding
+                    .decode_without_bom_handling_and_without_replacement(input)
+                    .map(|output| output.into_owned())
+            };
+            let whole = match decode(&input) {
+                Some(whole) => whole,
+                None => continue, // the replacement encoding
+            };
+            let mut d = wrap.raw_decoder();
+            let mut output = String::new();
+            for i in 0..input.len() {
+                let (processed, err) = d.raw_feed(&input[i..i + 1], &mut output);
+                assert!(err.is_none(), "{} at byte {}", wrap.name, i);
+                let complete = decode(&input[..i + 1]).is_some();
+                assert_eq!(processed == 1, complete, "{} at byte {}", wrap.name, i);
+            }
+            assert!(d.raw_finish(&mut output).is_none());
+            assert_eq!(output, whole);
+        }
+    }
+
+    #[test]
+    fn test_decode_bytes_fed_again() {
+        // the bytes after a malformed sequence are processed once fed again
+        let mut d = UTF_16BE.raw_decoder();
+        assert_feed_ok!(d, [], [0xd8, 0x00], "");
+        assert_feed_err!(d, [], [], [0x12, 0x34], "");
+        assert_feed_ok!(d, [0x12, 0x34], [], "\u{1234}");
+        assert_finish_ok!(d, "");
+
+        let mut d = ISO_2022_JP.raw_decoder();
+        assert_feed_err!(d, [], [0x1b], [0x24, 0x41], "");
+        assert_feed_ok!(d, [0x24, 0x41, 0x1b, 0x24, 0x42], [0x30], "$A");
+        assert_feed_ok!(d, [0x21, 0x30, 0x21], [0x1b], "\u{4e9c}\u{4e9c}");
+        assert_finish_err!(d, "");
+    }
+
+    #[test]
+    fn test_encoding_rs_round_trip() {
+        for wrap in &WRAPS[..] {
+            let encoding = from_encoding_rs(wrap.encoding);
+            assert_eq!(encoding.name(), wrap.name);
+            assert_eq!(to_encoding_rs(encoding), Some(wrap.encoding));
+        }
+        // the encodings implemented in this crate
+        assert_eq!(to_encoding_rs(::all::ISO_8859_1), None);
+        assert_eq!(to_encoding_rs(::all::HZ), None);
+        assert_eq!(to_encoding_rs(::all::ASCII), None);
+    }
+
+    #[test]
+    fn test_encoding_rs_conversions() {
+        use std::convert::TryInto;
+
+        for wrap in &WRAPS[..] {
+            let encoding: EncodingRef = wrap.encoding.into();
+            assert_eq!(encoding.name(), wrap.name);
+            let back: &'static encoding_rs::Encoding = encoding.try_into().unwrap();
+            assert_eq!(back, wrap.encoding);
+        }
+        let ascii: EncodingRef = ::all::ASCII;
+        let ascii: Result<&'static encoding_rs::Encoding, _> = ascii.try_into();
+        assert_eq!(ascii, Err(NoEncodingRsCounterpart));
+    }
+
+    #[test]
+    fn test_to_encoding_rs_mapping() {
+        assert_eq!(to_encoding_rs_mapping(&SHIFT_JIS),
+                   Some(EncodingRsMapping::Exact(encoding_rs::SHIFT_JIS)));
+        assert_eq!(to_encoding_rs_mapping(::all::ASCII),
+                   Some(EncodingRsMapping::Superset(encoding_rs::WINDOWS_1252)));
+        assert_eq!(to_encoding_rs_mapping(::all::HZ), None);
+        assert_eq!(to_encoding_rs_mapping(&UTF_16LE),
+                   Some(EncodingRsMapping::DecodeOnly(encoding_rs::UTF_16LE)));
+        assert_eq!(to_encoding_rs_mapping(&UTF_16BE),
+                   Some(EncodingRsMapping::DecodeOnly(encoding_rs::UTF_16BE)));
+        assert_eq!(to_encoding_rs_mapping(&REPLACEMENT),
+                   Some(EncodingRsMapping::DecodeOnly(encoding_rs::REPLACEMENT)));
+        assert_eq!(to_encoding_rs(&UTF_16BE), Some(encoding_rs::UTF_16BE));
+
+        // an exact mapping encodes the same, which UTF-16 doesn't.
+        let text = "A\u{e9}\u{3042}\u{1f600}";
+        for wrap in &WRAPS[..] {
+            let mapping = wrap.encoding_rs_mapping().unwrap();
+            let (bytes, _, _) = mapping.encoding().encode(text);
+            let same = wrap.encode(text, EncoderTrap::NcrEscape).unwrap() == &bytes[..];
+            assert!(same || !mapping.is_exact(), "{}", wrap.name);
+            assert!(!same || wrap.utf16_endianness().is_none(), "{}", wrap.name);
+        }
+    }
+
+    /// An encoding implemented elsewhere which declares its encoding_rs counterpart.
+    struct ThirdPartyLatin1;
+
+    impl Encoding for ThirdPartyLatin1 {
+        fn name(&self) -> &'static str {
+            "third-party-windows-1252"
+        }
+
+        fn encoding_rs_mapping(&self) -> Option<EncodingRsMapping> {
+            Some(EncodingRsMapping::Exact(encoding_rs::WINDOWS_1252))
+        }
+
+        fn raw_encoder(&self) -> Box<RawEncoder> {
+            WINDOWS_1252.raw_encoder()
+        }
+
+        fn raw_decoder(&self) -> Box<RawDecoder> {
+            WINDOWS_1252.raw_decoder()
+        }
+    }
+
+    #[test]
+    fn test_to_encoding_rs_third_party() {
+        static THIRD_PARTY: ThirdPartyLatin1 = ThirdPartyLatin1;
+        assert_eq!(to_encoding_rs(&THIRD_PARTY), Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(from_encoding_rs(encoding_rs::WINDOWS_1252).name(), "windows-1252");
+    }
+
+    #[test]
+    fn test_wraps_by_name() {
+        for wrap in &WRAPS[..] {
+            let found = WRAPS_BY_NAME[name_slot(wrap.encoding.name())].unwrap();
+            assert!(found.encoding == wrap.encoding, "{} is not in its slot", wrap.name);
+        }
+        assert_eq!(WRAPS_BY_NAME.iter().filter(|wrap| wrap.is_some()).count(), WRAPS.len());
+    }
+
+    #[test]
+    fn test_is_ascii_compatible() {
+        for wrap in &WRAPS[..] {
+            let compatible = wrap.is_ascii_compatible();
+            assert_eq!(compatible, wrap.raw_decoder().is_ascii_compatible(), "{}", wrap.name);
+            // the encoder of the replacement encoding writes UTF-8.
+            assert!(!compatible || wrap.raw_encoder().is_ascii_compatible(), "{}", wrap.name);
+        }
+    }
+
+    #[test]
+    fn test_encode_with_stateful_trap() {
+        for encoding in &[&WINDOWS_1252, &ISO_2022_JP] {
+            let mut unmappables = Vec::new();
+            {
+                let mut trap = |_: &mut RawEncoder, input: &str, output: &mut ByteWriter| {
+                    unmappables.push(input.to_string());
+                    output.write_bytes(b"?");
+                    true
+                };
+                assert_eq!(encoding.encode_with("A\u{e01}B\u{ac00}C", &mut trap),
+                           Ok(b"A?B?C".to_vec()));
+                let mut copying = CopyingByteWriter(Vec::new());
+                encoding.encode_to_with("A\u{e01}B\u{ac00}C", &mut trap, &mut copying).unwrap();
+                assert_eq!(copying.0, b"A?B?C");
+            }
+            assert_eq!(unmappables, ["\u{e01}", "\u{ac00}", "\u{e01}", "\u{ac00}"]);
+        }
+
+        assert!(WINDOWS_1252.encode_with("\u{ac00}", &mut |_, _, _| false).is_err());
+    }
+
+    #[test]
+    fn test_decode_with_stateful_trap() {
+        let mut problems = Vec::new();
+        {
+            let mut trap = |_: &mut RawDecoder, input: &[u8], output: &mut StringWriter| {
+                problems.push(input.to_vec());
+                output.write_str("?");
+                true
+            };
+            assert_eq!(EUC_JP.decode_with(b"A\xffB\x8e", &mut trap), Ok("A?B?".to_string()));
+            let mut copying = CopyingStringWriter(String::new());
+            EUC_JP.decode_to_with(b"A\xffB\x8e", &mut trap, &mut copying).unwrap();
+            assert_eq!(copying.0, "A?B?");
+        }
+        assert_eq!(problems, [&b"\xff"[..], b"\x8e", b"\xff", b"\x8e"]);
+
+        assert!(EUC_JP.decode_with(b"A\xffB", &mut |_, _, _| false).is_err());
+    }
+
+    #[test]
+    fn test_decode_with_errors() {
+        assert_eq!(EUC_JP.decode_with_errors(b"A\xffB\x8e"),
+                   ("A\u{fffd}B\u{fffd}".to_string(), vec![(1, &b"\xff"[..]), (3, b"\x8e")]));
+        assert_eq!(UTF_8.decode_with_errors(b"\xe3\x81A\xe3\x81\x82\xc0\xafB\xf0"),
+                   ("\u{fffd}A\u{3042}\u{fffd}\u{fffd}B\u{fffd}".to_string(),
+                    vec![(0, &b"\xe3\x81"[..]), (6, b"\xc0"), (7, b"\xaf"), (9, b"\xf0")]));
+        assert_eq!(GB18030.decode_with_errors(b"\x84\x31\xa5\x30A"),
+                   ("\u{fffd}A".to_string(), vec![(0, &b"\x84\x31\xa5\x30"[..])]));
+        assert_eq!(UTF_8.decode_with_errors(b"A"), ("A".to_string(), vec![]));
+        for encoding in &[&EUC_JP, &UTF_8, &GB18030, &ISO_2022_JP] {
+            let input = b"\x1b$B\xff\x81\x30\x81\xe3\x81";
+            assert_eq!(encoding.decode_with_errors(input).0,
+                       encoding.decode(input, DecoderTrap::Replace).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_encode_checked() {
+        let err = WINDOWS_1252.encode_checked("A\u{3042}B", EncoderTrap::Strict).unwrap_err();
+        assert_eq!(err,
+                   EncodingError {
+                       kind: EncodingErrorKind::Unmappable,
+                       offset: 1,
+                       sequence: "\u{3042}".as_bytes().to_vec(),
+                       encoding: "windows-1252",
+                   });
+        assert_eq!(err.to_string(), "unrepresentable character U+3042 at byte 1 in windows-1252");
+        assert_eq!(WINDOWS_1252.encode_checked("A\u{3042}B", EncoderTrap::Replace),
+                   Ok(b"A?B".to_vec()));
+
+        // the actual character rather than U+FFFD given to the trap
+        let err = ISO_2022_JP.encode_checked("\u{3042}\x1b", EncoderTrap::Strict).unwrap_err();
+        assert_eq!((err.offset, err.unmappable_char()), (3, Some('\x1b')));
+
+        assert_eq!(UTF_16LE.encode_checked("A", EncoderTrap::Strict), Ok(b"A\x00".to_vec()));
+    }
+
+    #[test]
+    fn test_replacing() {
+        let geta = "\u{3013}";
+        assert_eq!(SHIFT_JIS.encode_replacing("A\u{e9}B", geta), Ok(b"A\x81\xacB".to_vec()));
+        assert_eq!(ISO_2022_JP.encode_replacing("A\u{e9}B", geta),
+                   Ok(b"A\x1b$B\x22\x2e\x1b(BB".to_vec()));
+        assert_eq!(WINDOWS_1252.encode_replacing("A\u{3042}B", "\u{1a}"), Ok(b"A\x1aB".to_vec()));
+        assert_eq!(WINDOWS_1252.encode_replacing("A\u{3042}B", ""), Ok(b"AB".to_vec()));
+
+        assert_eq!(WINDOWS_1252.encode_replacing("A\u{3042}B", geta),
+                   Err("cannot reencode a replacement string".into()));
+        let mut output = Vec::new();
+        assert_eq!(WINDOWS_1252.encode_to_replacing("A\u{3042}B", geta, &mut output),
+                   Err("cannot reencode a replacement string".into()));
+
+        assert_eq!(EUC_JP.decode_replacing(b"A\xffB", geta), Ok("A\u{3013}B".to_string()));
+        assert_eq!(EUC_JP.decode_replacing(b"A\xffB", ""), Ok("AB".to_string()));
+    }
+
+    #[test]
+    fn test_first_unmappable() {
+        assert_eq!(SHIFT_JIS.first_unmappable("\u{65e5}\u{672c}\u{8a9e}abc"), None);
+        assert!(SHIFT_JIS.can_encode("\u{ff8a}\u{ff9d}\u{ff76}\u{ff78}"));
+        assert_eq!(SHIFT_JIS.first_unmappable("abc\u{e9}\u{20ac}"), Some((3, '\u{e9}')));
+        assert!(WINDOWS_1252.can_encode("\u{20ac}\u{e9}"));
+        assert_eq!(WINDOWS_1252.first_unmappable("\u{e9}\u{3042}"), Some((2, '\u{3042}')));
+        assert_eq!(ISO_2022_JP.first_unmappable("\u{3042}\x1b"), Some((3, '\x1b')));
+        assert!(UTF_8.can_encode("\u{3042}\u{1f600}"));
+        assert!(UTF_16BE.can_encode("\u{3042}\u{1f600}"));
+        assert!(GB18030.can_encode("\u{3042}\u{1f600}"));
+    }
+
+    #[test]
+    fn test_decode_checked() {
+        let err = EUC_JP.decode_checked(b"A\xffB\x8e", DecoderTrap::Strict).unwrap_err();
+        assert_eq!(err,
+                   EncodingError {
+                       kind: EncodingErrorKind::Malformed,
+                       offset: 1,
+                       sequence: b"\xff".to_vec(),
+                       encoding: "euc-jp",
+                   });
+        assert_eq!(err.unmappable_char(), None);
+        assert_eq!(err.to_string(), "invalid sequence FF at byte 1 in euc-jp");
+        let err = EUC_JP.decode_checked(b"AB\x8e", DecoderTrap::Strict).unwrap_err();
+        assert_eq!((err.offset, err.sequence), (2, b"\x8e".to_vec()));
+        assert_eq!(EUC_JP.decode_checked(b"A\xffB\x8e", DecoderTrap::Replace),
+                   Ok("A\u{fffd}B\u{fffd}".to_string()));
+    }
+
+    fn is_borrowed<B: ?Sized + ToOwned>(cow: &Cow<B>) -> bool {
+        match *cow {
+            Cow::Borrowed(_) => true,
+            Cow::Owned(_) => false,
+        }
+    }
+
+    #[test]
+    fn test_encode_cow() {
+        for encoding in &[&UTF_8, &WINDOWS_1252, &SHIFT_JIS, &GB18030] {
+            let out = encoding.encode_cow(testutils::ASCII_TEXT, EncoderTrap::Strict).unwrap();
+            assert!(is_borrowed(&out), "{} should borrow ASCII", encoding.name());
+            assert_eq!(&out[..], testutils::ASCII_TEXT.as_bytes());
+        }
+        let out = UTF_8.encode_cow(testutils::JAPANESE_TEXT, EncoderTrap::Strict).unwrap();
+        assert!(is_borrowed(&out));
+        for encoding in &[&SHIFT_JIS, &ISO_2022_JP, &UTF_16LE] {
+            for &trap in &[EncoderTrap::Strict, EncoderTrap::NcrEscape] {
+                let out = encoding.encode_cow(testutils::JAPANESE_TEXT, trap).unwrap();
+                assert!(!is_borrowed(&out));
+                assert_eq!(out.into_owned(),
+                           encoding.encode(testutils::JAPANESE_TEXT, trap).unwrap());
+            }
+        }
+        let out = UTF_16BE.encode_cow("A", EncoderTrap::Strict).unwrap();
+        assert_eq!(&out[..], b"\x00A");
+        assert!(WINDOWS_1252.encode_cow("\u{3042}", EncoderTrap::Strict).is_err());
+    }
+
+    #[test]
+    fn test_decode_cow() {
+        for encoding in &[&UTF_8, &WINDOWS_1252, &SHIFT_JIS, &ISO_2022_JP] {
+            for &trap in &[DecoderTrap::Strict, DecoderTrap::Replace] {
+                let out = encoding.decode_cow(testutils::ASCII_TEXT.as_bytes(), trap).unwrap();
+                assert!(is_borrowed(&out), "{} should borrow ASCII", encoding.name());
+                assert_eq!(out, testutils::ASCII_TEXT);
+            }
+        }
+        let out = UTF_8.decode_cow(testutils::JAPANESE_TEXT.as_bytes(), DecoderTrap::Strict).unwrap();
+        assert!(is_borrowed(&out));
+        let bytes = SHIFT_JIS.encode(testutils::JAPANESE_TEXT, EncoderTrap::Strict).unwrap();
+        let out = SHIFT_JIS.decode_cow(&bytes, DecoderTrap::Strict).unwrap();
+        assert!(!is_borrowed(&out));
+        assert_eq!(out, testutils::JAPANESE_TEXT);
+
+        assert!(UTF_8.decode_cow(b"A\xffB", DecoderTrap::Strict).is_err());
+        assert_eq!(UTF_8.decode_cow(b"A\xffB", DecoderTrap::Replace).unwrap(), "A\u{fffd}B");
+        assert_eq!(UTF_8.decode_cow(b"A\xffB", DecoderTrap::Ignore).unwrap(), "AB");
+    }
+
+    #[bench]
+    fn bench_encode_cow_ascii(bencher: &mut test::Bencher) {
+        let s = testutils::ASCII_TEXT;
+        bencher.bytes = s.len() as u64;
+        bencher.iter(|| {
+            test::black_box(WINDOWS_1252.encode_cow(test::black_box(s), EncoderTrap::Strict)
+                                        .unwrap());
+        })
+    }
+
+    #[bench]
+    fn bench_decode_cow_ascii(bencher: &mut test::Bencher) {
+        let s = testutils::ASCII_TEXT.as_bytes();
+        bencher.bytes = s.len() as u64;
+        bencher.iter(|| {
+            test::black_box(WINDOWS_1252.decode_cow(test::black_box(s), DecoderTrap::Strict)
+                                        .unwrap());
+        })
+    }
+
+    // The writers are reused across iterations, so that the benchmarks
+    // measure the copying rather than the allocation.
+
+    #[bench]
+    fn bench_encode_to_copying_writer(bencher: &mut test::Bencher) {
+        let s = testutils::JAPANESE_TEXT;
+        let mut writer = CopyingByteWriter(Vec::new());
+        bencher.bytes = s.len() as u64;
+        bencher.iter(|| {
+            writer.0.clear();
+            EUC_JP.encode_to(s, EncoderTrap::Strict, &mut writer).unwrap();
+            test::black_box(&writer.0);
+        })
+    }
+
+    #[bench]
+    fn bench_encode_to_slice_writer(bencher: &mut test::Bencher) {
+        let s = testutils::JAPANESE_TEXT;
+        let mut writer = SliceByteWriter::new();
+        bencher.bytes = s.len() as u64;
+        bencher.iter(|| {
+            writer.len = 0;
+            EUC_JP.encode_to(s, EncoderTrap::Strict, &mut writer).unwrap();
+            test::black_box(writer.as_bytes());
+        })
+    }
+
+    #[bench]
+    fn bench_decode_to_copying_writer(bencher: &mut test::Bencher) {
+        let s = EUC_JP.encode(testutils::JAPANESE_TEXT, EncoderTrap::Strict).unwrap();
+        let mut writer = CopyingStringWriter(String::new());
+        bencher.bytes = s.len() as u64;
+        bencher.iter(|| {
+            writer.0.clear();
+            EUC_JP.decode_to(&s, DecoderTrap::Strict, &mut writer).unwrap();
+            test::black_box(&writer.0);
+        })
+    }
+
+    #[bench]
+    fn bench_decode_to_slice_writer(bencher: &mut test::Bencher) {
+        let s = EUC_JP.encode(testutils::JAPANESE_TEXT, EncoderTrap::Strict).unwrap();
+        let mut writer = SliceStringWriter::new();
+        bencher.bytes = s.len() as u64;
+        bencher.iter(|| {
+            writer.len = 0;
+            EUC_JP.decode_to(&s, DecoderTrap::Strict, &mut writer).unwrap();
+            test::black_box(&writer.buffer[..writer.len]);
+        })
+    }
+}
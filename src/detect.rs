@@ -0,0 +1,714 @@
+// Copyright 2016 Mozilla Foundation. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE.txt or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Statistical detection of the encoding of unlabeled text.
+//!
+//! `Detector` decodes the input with every candidate encoding at once
+//! and scores the resulting text with a simple model of the languages
+//! the encoding is used for. Malformed sequences, control characters
+//! and symbols in the middle of words, mixed scripts and letters foreign
+//! to every language of the encoding count against a candidate.
+//! The CJK encodings also favor the characters in the frequently used
+//! parts of their character sets.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use compat;
+use types::{Encoding, EncodingRef, RawDecoder, RawEncoder};
+
+/// The penalty for a malformed sequence, which rules out the candidate for any non-trivial input.
+const MALFORMED_PENALTY: f64 = 50.0;
+
+/// The score difference that makes one candidate `e` times as likely as another.
+const CONFIDENCE_SCALE: f64 = 2.0;
+
+const FRENCH: &'static str = "àâæçéèêëîïôœùûüÿ";
+const GERMAN: &'static str = "äöüß";
+const SPANISH: &'static str = "áéíñóúü";
+const PORTUGUESE: &'static str = "áâãàçéêíóôõú";
+const ITALIAN: &'static str = "àèéìíîòóùú";
+const DUTCH: &'static str = "áéèëïóöü";
+const DANISH_NORWEGIAN: &'static str = "æøåéó";
+const SWEDISH: &'static str = "åäöé";
+const FINNISH: &'static str = "äöåšž";
+const ICELANDIC: &'static str = "áéíóúýþæöð";
+const CATALAN: &'static str = "àçèéíïòóúü";
+const POLISH: &'static str = "ąćęłńóśźż";
+const CZECH: &'static str = "áčďéěíňóřšťúůýž";
+const SLOVAK: &'static str = "áäčďéíĺľňóôŕšťúýž";
+const HUNGARIAN: &'static str = "áéíóöőúüű";
+const SLOVENE_CROATIAN: &'static str = "čćđšž";
+const ROMANIAN_CEDILLA: &'static str = "ăâîşţ";
+const ROMANIAN: &'static str = "ăâîșț";
+const LITHUANIAN: &'static str = "ąčęėįšųūž";
+const LATVIAN: &'static str = "āčēģīķļņšūž";
+const ESTONIAN: &'static str = "äõöüšž";
+const TURKISH: &'static str = "çğıöşüâîû";
+const MALTESE: &'static str = "ċġħżàèìòù";
+const ESPERANTO: &'static str = "ĉĝĥĵŝŭ";
+const IRISH_WELSH: &'static str = "áéíóúḃċḋḟġṁṗṡṫŵŷâêîôû";
+const VIETNAMESE: &'static str = "àáâãèéêìíòóôõùúýăđơư\u{300}\u{301}\u{303}\u{309}\u{323}";
+const RUSSIAN: &'static str = "абвгдеёжзийклмнопрстуфхцчшщъыьэюя";
+const UKRAINIAN: &'static str = "абвгґдеєжзиіїйклмнопрстуфхцчшщьюя";
+const BELARUSIAN: &'static str = "абвгдеёжзійклмнопрстуўфхцчшыьэюя";
+const BULGARIAN: &'static str = "абвгдежзийклмнопрстуфхцчшщъьюя";
+const SERBIAN: &'static str = "абвгдђежзијклљмнњопрстћуфхцчџш";
+const MACEDONIAN: &'static str = "абвгдѓежзѕијклљмнњопрстќуфхцчџш";
+const GREEK: &'static str = "αβγδεζηθικλμνξοπρσςτυφχψωάέήίόύώϊϋΐΰ";
+const HEBREW: &'static str = "אבגדהוזחטיךכלםמןנסעףפץצקרשת\u{5b0}\u{5b1}\u{5b2}\u{5b3}\u{5b4}\
+                               \u{5b5}\u{5b6}\u{5b7}\u{5b8}\u{5b9}\u{5bb}\u{5bc}\u{5c1}\u{5c2}";
+const ARABIC: &'static str = "ءآأؤإئابةتثجحخدذرزسشصضطظعغفقكلمنهوىيپچژگ\u{64b}\u{64c}\u{64d}\
+                               \u{64e}\u{64f}\u{650}\u{651}\u{652}";
+const THAI: &'static str = "กขฃคฅฆงจฉชซฌญฎฏฐฑฒณดตถทธนบปผฝพฟภมยรฤลฦวศษสหฬอฮะัาำิีึืฺุู\
+                             เแโใไๅๆ็่้๊๋์ํ";
+
+const WESTERN: &'static [&'static str] = &[FRENCH, GERMAN, SPANISH, PORTUGUESE, ITALIAN, DUTCH,
+                                           DANISH_NORWEGIAN, SWEDISH, FINNISH, ICELANDIC,
+                                           CATALAN];
+const CENTRAL: &'static [&'static str] = &[POLISH, CZECH, SLOVAK, HUNGARIAN, SLOVENE_CROATIAN,
+                                           ROMANIAN_CEDILLA];
+const BALTIC: &'static [&'static str] = &[LITHUANIAN, LATVIAN, ESTONIAN];
+const CYRILLIC: &'static [&'static str] = &[RUSSIAN, UKRAINIAN, BELARUSIAN, BULGARIAN, SERBIAN,
+                                            MACEDONIAN];
+
+/// How the text decoded with a candidate encoding is scored.
+#[derive(Clone, Copy)]
+enum Model {
+    Utf8,
+    Japanese,
+    Korean,
+    SimplifiedChinese,
+    TraditionalChinese,
+    /// A single-byte encoding used for any of the languages,
+    /// each given as the lowercase non-ASCII letters in its alphabet.
+    SingleByte(&'static [&'static str]),
+}
+
+/// The candidates in the order of preference when they score the same.
+static CANDIDATES: &'static [(EncodingRef, Model)] =
+    &[(&compat::WINDOWS_1252, Model::SingleByte(WESTERN)),
+      (&compat::UTF_8, Model::Utf8),
+      (&compat::SHIFT_JIS, Model::Japanese),
+      (&compat::EUC_JP, Model::Japanese),
+      (&compat::ISO_2022_JP, Model::Japanese),
+      (&compat::EUC_KR, Model::Korean),
+      (&compat::GBK, Model::SimplifiedChinese),
+      (&compat::BIG5, Model::TraditionalChinese),
+      (&compat::WINDOWS_1250, Model::SingleByte(CENTRAL)),
+      (&compat::WINDOWS_1251, Model::SingleByte(CYRILLIC)),
+      (&compat::WINDOWS_1253, Model::SingleByte(&[GREEK])),
+      (&compat::WINDOWS_1254, Model::SingleByte(&[TURKISH])),
+      (&compat::WINDOWS_1255, Model::SingleByte(&[HEBREW])),
+      (&compat::WINDOWS_1256, Model::SingleByte(&[ARABIC])),
+      (&compat::WINDOWS_1257, Model::SingleByte(BALTIC)),
+      (&compat::WINDOWS_1258, Model::SingleByte(&[VIETNAMESE])),
+      (&compat::WINDOWS_874, Model::SingleByte(&[THAI])),
+      (&compat::KOI8_R, Model::SingleByte(CYRILLIC)),
+      (&compat::KOI8_U, Model::SingleByte(CYRILLIC)),
+      (&compat::IBM866, Model::SingleByte(CYRILLIC)),
+      (&compat::ISO_8859_2, Model::SingleByte(CENTRAL)),
+      (&compat::ISO_8859_5, Model::SingleByte(CYRILLIC)),
+      (&compat::ISO_8859_7, Model::SingleByte(&[GREEK])),
+      (&compat::ISO_8859_8, Model::SingleByte(&[HEBREW])),
+      (&compat::ISO_8859_13, Model::SingleByte(BALTIC)),
+      (&compat::ISO_8859_4, Model::SingleByte(BALTIC)),
+      (&compat::ISO_8859_15, Model::SingleByte(WESTERN)),
+      (&compat::ISO_8859_3, Model::SingleByte(&[MALTESE, ESPERANTO, TURKISH])),
+      (&compat::ISO_8859_10, Model::SingleByte(&[DANISH_NORWEGIAN, SWEDISH, FINNISH,
+                                                  ICELANDIC])),
+      (&compat::ISO_8859_14, Model::SingleByte(&[IRISH_WELSH])),
+      (&compat::ISO_8859_16, Model::SingleByte(&[ROMANIAN, POLISH, HUNGARIAN])),
+      (&compat::ISO_8859_6, Model::SingleByte(&[ARABIC])),
+      (&compat::X_MAC_CYRILLIC, Model::SingleByte(CYRILLIC))];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Hebrew,
+    Arabic,
+    Thai,
+    /// Combining marks, which belong to the script of the preceding letter.
+    Mark,
+    Other,
+}
+
+fn script(c: char) -> Script {
+    match c {
+        'A'..='Z' | 'a'..='z' | '\u{c0}'..='\u{24f}' | '\u{1e00}'..='\u{1eff}' => Script::Latin,
+        '\u{300}'..='\u{36f}' | '\u{591}'..='\u{5c7}' | '\u{64b}'..='\u{652}' |
+        '\u{e31}' | '\u{e34}'..='\u{e3a}' | '\u{e47}'..='\u{e4e}' => Script::Mark,
+        '\u{370}'..='\u{3ff}' => Script::Greek,
+        '\u{400}'..='\u{4ff}' => Script::Cyrillic,
+        '\u{5d0}'..='\u{5ff}' => Script::Hebrew,
+        '\u{600}'..='\u{6ff}' => Script::Arabic,
+        '\u{e00}'..='\u{e7f}' => Script::Thai,
+        _ => Script::Other,
+    }
+}
+
+/// Returns true for vowels in the scripts whose words almost always have one.
+fn is_vowel(c: char) -> bool {
+    "aeiouyàáâãäåæèéêëìíîïòóôõöøùúûüýÿœąęėįųūāēīőűůěăаеёиоуыэюяіїєαεηιουωάέήίόύώϊϋΐΰ"
+        .contains(c)
+}
+
+/// Returns true for letters and combining marks, which make up words.
+fn is_word_char(c: char) -> bool {
+    c.is_alphabetic() || script(c) == Script::Mark
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CjkClass {
+    Kana,
+    HalfwidthKana,
+    Hangul,
+    Han,
+    Punctuation,
+    Private,
+    Other,
+}
+
+fn cjk_class(c: char) -> CjkClass {
+    match c {
+        '\u{3041}'..='\u{30ff}' => CjkClass::Kana,
+        '\u{ff61}'..='\u{ff9f}' => CjkClass::HalfwidthKana,
+        '\u{ac00}'..='\u{d7a3}' => CjkClass::Hangul,
+        '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' => CjkClass::Han,
+        '\u{3000}'..='\u{303f}' | '\u{ff01}'..='\u{ff60}' | '\u{ffe0}'..='\u{ffe6}' => {
+            CjkClass::Punctuation
+        }
+        '\u{e000}'..='\u{f8ff}' => CjkClass::Private,
+        _ => CjkClass::Other,
+    }
+}
+
+/// Returns true for the non-ASCII punctuation common in running text.
+fn is_common_punctuation(c: char) -> bool {
+    match c {
+        '\u{a0}' | '\u{ad}' | '\u{b7}' | '\u{2018}'..='\u{201e}' | '\u{ab}' | '\u{bb}' |
+        '\u{2013}' | '\u{2014}' | '\u{2026}' | '\u{20ac}' | '\u{b0}' | '\u{a1}' | '\u{bf}' |
+        '\u{2022}' | '\u{2039}' | '\u{203a}' | '\u{2116}' | '\u{a7}' => true,
+        _ => false,
+    }
+}
+
+/// The state of scoring the text decoded with one candidate.
+struct Candidate {
+    encoding: EncodingRef,
+    model: Model,
+    decoder: Box<RawDecoder>,
+    /// The encoder that tells where CJK characters are in the character set.
+    encoder: Option<Box<RawEncoder>>,
+    /// The score shared by all languages of the model.
+    score: f64,
+    /// The score of each language of a single-byte model.
+    language_scores: Vec<f64>,
+    /// The last character decoded.
+    prev: char,
+    /// Whether a symbol follows a letter, which is bad if another letter follows.
+    symbol_after_letter: bool,
+    /// Whether the current word has a non-ASCII letter, a vowel and how many letters.
+    word_non_ascii: bool,
+    word_vowel: bool,
+    word_len: usize,
+    /// Whether a space follows a CJK character, which is bad if another one follows.
+    space_after_cjk: bool,
+    /// The number of non-ASCII characters since the last ASCII character.
+    run: usize,
+}
+
+impl Candidate {
+    fn new(encoding: EncodingRef, model: Model) -> Candidate {
+        let encoder = match model {
+            Model::Japanese => Some(compat::EUC_JP.raw_encoder()),
+            Model::Korean | Model::SimplifiedChinese | Model::TraditionalChinese => {
+                Some(encoding.raw_encoder())
+            }
+            _ => None,
+        };
+        let languages = match model {
+            Model::SingleByte(languages) => languages.len(),
+            _ => 0,
+        };
+        Candidate {
+            encoding: encoding,
+            model: model,
+            decoder: encoding.raw_decoder(),
+            encoder: encoder,
+            score: 0.0,
+            language_scores: vec![0.0; languages],
+            prev: ' ',
+            symbol_after_letter: false,
+            word_non_ascii: false,
+            word_vowel: false,
+            word_len: 0,
+            space_after_cjk: false,
+            run: 0,
+        }
+    }
+
+    fn total_score(&self) -> f64 {
+        let language_score = self.language_scores.iter().cloned().fold(None, |max, s| {
+            match max {
+                Some(max) if max >= s => Some(max),
+                _ => Some(s),
+            }
+        });
+        self.score + language_score.unwrap_or(0.0)
+    }
+
+    /// Decodes `input`, which follows the bytes ending with `before`, and scores the result.
+    fn feed(&mut self, before: &[u8], input: &[u8]) {
+        let mut before = before;
+        let mut input = Cow::Borrowed(input);
+        let mut decoded = String::new();
+        let mut remaining = 0;
+        loop {
+            let (_, err) = self.decoder.raw_feed(&input[remaining..], &mut decoded);
+            let err = match err {
+                Some(err) => err,
+                None => break,
+            };
+            self.score -= MALFORMED_PENALTY;
+            let upto = remaining as isize + err.upto;
+            if upto >= 0 {
+                remaining = upto as usize;
+            } else {
+                // the malformed sequence has ended in the prior input.
+                self.score_str(&decoded);
+                decoded.clear();
+                let back = ::std::cmp::min((-upto) as usize, before.len());
+                let mut again = before[before.len() - back..].to_vec();
+                again.extend_from_slice(&input);
+                input = Cow::Owned(again);
+                before = &[];
+                remaining = 0;
+            }
+        }
+        self.score_str(&decoded);
+    }
+
+    /// Scores the end of the input, where a multibyte sequence may have been cut short.
+    fn finish(&mut self) {
+        let mut decoded = String::new();
+        if self.decoder.raw_finish(&mut decoded).is_some() {
+            self.score -= MALFORMED_PENALTY;
+        }
+        self.score_str(&decoded);
+    }
+
+    fn score_str(&mut self, decoded: &str) {
+        for c in decoded.chars() {
+            match self.model {
+                Model::Utf8 => {
+                    if !c.is_ascii() {
+                        self.score += 2.0;
+                    }
+                }
+                Model::SingleByte(languages) => self.score_single_byte(languages, c),
+                _ => self.score_cjk(c),
+            }
+            self.prev = c;
+        }
+    }
+
+    fn score_single_byte(&mut self, languages: &'static [&'static str], c: char) {
+        let prev = self.prev;
+        if is_word_char(c) {
+            let lower = c.to_lowercase().next().unwrap_or(c);
+            self.word_len += 1;
+            self.word_non_ascii |= !c.is_ascii();
+            self.word_vowel |= is_vowel(lower);
+            if self.symbol_after_letter {
+                self.score -= 3.0;
+            }
+            self.symbol_after_letter = false;
+            if is_word_char(prev) {
+                let (script, prev_script) = (script(c), script(prev));
+                if script != prev_script && script != Script::Mark && prev_script != Script::Mark {
+                    self.score -= 3.0;
+                }
+                if script == Script::Latin && !c.is_ascii() && !prev.is_ascii() &&
+                   prev_script == Script::Latin {
+                    self.score -= 1.0;
+                }
+                if c.is_uppercase() && prev.is_lowercase() && !(c.is_ascii() && prev.is_ascii()) {
+                    self.score -= 2.0;
+                }
+                // final sigma only ends words.
+                if prev == '\u{3c2}' {
+                    self.score -= 3.0;
+                }
+            }
+            if !c.is_ascii() {
+                for (letters, score) in languages.iter().zip(self.language_scores.iter_mut()) {
+                    *score += if letters.contains(lower) { 1.0 } else { -2.0 };
+                }
+            }
+            return;
+        }
+        // the end of a word.
+        if self.word_non_ascii && !self.word_vowel && self.word_len >= 3 {
+            match script(prev) {
+                Script::Latin | Script::Greek | Script::Cyrillic => self.score -= 2.0,
+                _ => {}
+            }
+        }
+        self.word_non_ascii = false;
+        self.word_vowel = false;
+        self.word_len = 0;
+        if c.is_ascii() {
+            self.symbol_after_letter = false;
+        } else if c >= '\u{80}' && c <= '\u{9f}' {
+            self.score -= 10.0;
+            self.symbol_after_letter = false;
+        } else if c == '\u{2019}' || c == '\u{b7}' || c == '\u{ad}' {
+            // may appear within words.
+            self.symbol_after_letter = false;
+        } else {
+            if !is_common_punctuation(c) {
+                self.score -= 0.5;
+            }
+            self.symbol_after_letter = is_word_char(prev);
+        }
+    }
+
+    fn score_cjk(&mut self, c: char) {
+        if c.is_ascii() {
+            self.run = 0;
+            let after_cjk = match cjk_class(self.prev) {
+                CjkClass::Kana | CjkClass::Hangul | CjkClass::Han => true,
+                _ => false,
+            };
+            self.space_after_cjk = c == ' ' && after_cjk;
+            return;
+        }
+        self.run += 1;
+        let class = cjk_class(c);
+        let is_word = class == CjkClass::Kana || class == CjkClass::Hangul ||
+                      class == CjkClass::Han;
+        if self.space_after_cjk && is_word {
+            match self.model {
+                Model::Korean => {}
+                _ => self.score -= 1.0,
+            }
+        }
+        self.space_after_cjk = false;
+        let frequent = match class {
+            CjkClass::Hangul | CjkClass::Han => self.frequent(c),
+            _ => None,
+        };
+        self.score += match (self.model, class) {
+            (_, CjkClass::Private) => -5.0,
+            (_, CjkClass::Other) => -1.0,
+            (Model::Japanese, CjkClass::Kana) => 2.0,
+            (Model::Japanese, CjkClass::HalfwidthKana) => -1.0,
+            (Model::Japanese, CjkClass::Hangul) => -5.0,
+            (Model::Korean, CjkClass::Hangul) => if frequent == Some(true) { 2.0 } else { -1.0 },
+            (Model::Korean, CjkClass::Han) => -1.0,
+            (Model::Korean, CjkClass::Punctuation) => 0.0,
+            (_, CjkClass::Han) => {
+                match frequent {
+                    Some(true) => 1.0,
+                    Some(false) => 0.0,
+                    None => -1.0,
+                }
+            }
+            (_, CjkClass::Punctuation) => 1.0,
+            _ => -2.0,
+        };
+        if let Model::Korean = self.model {
+            // Korean puts spaces between words unlike Chinese and Japanese.
+            if self.run > 8 {
+                self.score -= 1.0;
+            }
+        }
+    }
+
+    /// Returns whether `c` is in the frequently used part of the character set,
+    /// or `None` if it is in an extension of the character set.
+    fn frequent(&mut self, c: char) -> Option<bool> {
+        let mut buf = [0; 4];
+        let mut bytes = Vec::with_capacity(2);
+        {
+            let encoder = self.encoder.as_mut().unwrap();
+            let (_, err) = encoder.raw_feed(c.encode_utf8(&mut buf), &mut bytes);
+            if err.is_some() || bytes.len() != 2 {
+                return None;
+            }
+        }
+        let (lead, trail) = (bytes[0], bytes[1]);
+        match self.model {
+            // EUC-JP: JIS X 0208 level 1 and level 2 kanji.
+            Model::Japanese => {
+                match lead {
+                    0xb0..=0xcf => Some(true),
+                    0xd0..=0xf4 => Some(false),
+                    _ => None,
+                }
+            }
+            // KS X 1001 hangul rather than the extension in windows-949.
+            Model::Korean => Some(lead >= 0xb0 && lead <= 0xc8 && trail >= 0xa1),
+            // GB2312 level 1 and level 2 hanzi rather than the extension in GBK.
+            Model::SimplifiedChinese if trail >= 0xa1 => {
+                match lead {
+                    0xb0..=0xd7 => Some(true),
+                    0xd8..=0xf7 => Some(false),
+                    _ => None,
+                }
+            }
+            // Big5 level 1 and level 2 hanzi rather than the extensions.
+            Model::TraditionalChinese => {
+                match lead {
+                    0xa4..=0xc5 => Some(true),
+                    0xc6 if trail <= 0x7e => Some(true),
+                    0xc9..=0xf9 => Some(false),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// An incremental detector of the encoding of unlabeled text.
+///
+/// It considers UTF-8, the Japanese, Korean and Chinese multibyte encodings,
+/// and the single-byte windows-125x, ISO-8859, KOI8 and DOS/Mac Cyrillic
+/// encodings. The input should not start with a Byte Order Mark (BOM);
+/// see `types::decode` for that.
+pub struct Detector {
+    candidates: Vec<Candidate>,
+    /// The last bytes fed, which some decoders need to be fed again
+    /// after a malformed sequence.
+    tail: Vec<u8>,
+}
+
+impl Detector {
+    /// Creates a new detector which has seen no input.
+    pub fn new() -> Detector {
+        Detector {
+            candidates: CANDIDATES.iter().map(|&(encoding, model)| Candidate::new(encoding, model))
+                                  .collect(),
+            tail: Vec::new(),
+        }
+    }
+
+    /// Feeds the next part of the input.
+    /// Multibyte sequences may be split across the calls.
+    pub fn feed(&mut self, input: &[u8]) {
+        for candidate in &mut self.candidates {
+            candidate.feed(&self.tail, input);
+        }
+        self.tail.extend_from_slice(input);
+        let excess = self.tail.len().saturating_sub(8);
+        self.tail.drain(..excess);
+    }
+
+    /// Signals the end of the input, so that a truncated multibyte sequence
+    /// at the end counts against the candidate. Any further input is
+    /// considered to start afresh.
+    pub fn finish(&mut self) {
+        for candidate in &mut self.candidates {
+            candidate.finish();
+        }
+        self.tail.clear();
+    }
+
+    /// Returns the candidate encodings ranked by the input so far,
+    /// each with a confidence between 0 and 1. The confidences add up to 1.
+    pub fn candidates(&self) -> Vec<(EncodingRef, f64)> {
+        let scores: Vec<f64> = self.candidates.iter().map(|c| c.total_score()).collect();
+        let max = scores.iter().cloned().fold(::std::f64::NEG_INFINITY, f64::max);
+        let weights: Vec<f64> = scores.iter()
+                                      .map(|&score| ((score - max) / CONFIDENCE_SCALE).exp())
+                                      .collect();
+        let sum: f64 = weights.iter().sum();
+        let mut ranked: Vec<(EncodingRef, f64, f64)> =
+            self.candidates
+                .iter()
+                .zip(scores.iter().zip(weights.iter()))
+                .map(|(c, (&score, &weight))| (c.encoding, score, weight / sum))
+                .collect();
+        // the sort is stable, so the candidates scoring the same stay in the order of preference.
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        ranked.into_iter().map(|(encoding, _, confidence)| (encoding, confidence)).collect()
+    }
+
+    /// Returns the most likely encoding of the input so far.
+    pub fn guess(&self) -> EncodingRef {
+        self.candidates()[0].0
+    }
+}
+
+/// Returns the most likely encoding of `input`.
+pub fn detect(input: &[u8]) -> EncodingRef {
+    let mut detector = Detector::new();
+    detector.feed(input);
+    detector.finish();
+    detector.guess()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use all;
+    use testutils;
+    use types::{EncoderTrap, EncodingRef};
+
+    static CORPUS: &'static [(&'static str, &'static str)] =
+        &[("fr", include_str!("examples/detect/fr.txt")),
+          ("de", include_str!("examples/detect/de.txt")),
+          ("es", include_str!("examples/detect/es.txt")),
+          ("pt", include_str!("examples/detect/pt.txt")),
+          ("pl", include_str!("examples/detect/pl.txt")),
+          ("cs", include_str!("examples/detect/cs.txt")),
+          ("hu", include_str!("examples/detect/hu.txt")),
+          ("lt", include_str!("examples/detect/lt.txt")),
+          ("tr", include_str!("examples/detect/tr.txt")),
+          ("ru", include_str!("examples/detect/ru.txt")),
+          ("uk", include_str!("examples/detect/uk.txt")),
+          ("el", include_str!("examples/detect/el.txt")),
+          ("he", include_str!("examples/detect/he.txt")),
+          ("ar", include_str!("examples/detect/ar.txt")),
+          ("th", include_str!("examples/detect/th.txt")),
+          ("ja", testutils::JAPANESE_TEXT),
+          ("ko", testutils::KOREAN_TEXT),
+          ("zh-hans", testutils::SIMPLIFIED_CHINESE_TEXT),
+          ("zh-hant", testutils::TRADITIONAL_CHINESE_TEXT)];
+
+    fn text(lang: &str) -> &'static str {
+        CORPUS.iter().find(|&&(l, _)| l == lang).unwrap().1
+    }
+
+    #[test]
+    fn test_corpus() {
+        let cases: &[(&str, EncodingRef)] = &[("fr", all::WINDOWS_1252),
+                                              ("fr", all::ISO_8859_15),
+                                              ("fr", all::UTF_8),
+                                              ("de", all::WINDOWS_1252),
+                                              ("es", all::WINDOWS_1252),
+                                              ("pt", all::WINDOWS_1252),
+                                              ("pl", all::WINDOWS_1250),
+                                              ("pl", all::ISO_8859_2),
+                                              ("cs", all::WINDOWS_1250),
+                                              ("cs", all::ISO_8859_2),
+                                              ("hu", all::WINDOWS_1250),
+                                              ("lt", all::WINDOWS_1257),
+                                              ("lt", all::ISO_8859_4),
+                                              ("tr", all::WINDOWS_1254),
+                                              ("ru", all::WINDOWS_1251),
+                                              ("ru", all::KOI8_R),
+                                              ("ru", all::ISO_8859_5),
+                                              ("ru", all::IBM866),
+                                              ("ru", all::MAC_CYRILLIC),
+                                              ("ru", all::UTF_8),
+                                              ("uk", all::WINDOWS_1251),
+                                              ("uk", all::KOI8_U),
+                                              ("el", all::WINDOWS_1253),
+                                              ("el", all::ISO_8859_7),
+                                              ("he", all::WINDOWS_1255),
+                                              ("ar", all::WINDOWS_1256),
+                                              ("th", all::WINDOWS_874),
+                                              ("ja", all::WINDOWS_31J),
+                                              ("ja", all::EUC_JP),
+                                              ("ja", all::ISO_2022_JP),
+                                              ("ja", all::UTF_8),
+                                              ("ko", all::WINDOWS_949),
+                                              ("zh-hans", all::GBK),
+                                              ("zh-hant", all::BIG5_2003)];
+        let mut failures = Vec::new();
+        for &(lang, encoding) in cases {
+            let input = encoding.encode(text(lang), EncoderTrap::Strict).unwrap();
+            let guess = detect(&input);
+            if guess.name() != encoding.name() {
+                failures.push(format!("{} in {} detected as {}",
+                                      lang, encoding.name(), guess.name()));
+            }
+        }
+        assert!(failures.is_empty(), "{:?}", failures);
+    }
+
+    #[test]
+    fn test_incremental() {
+        for &(input, encoding) in &[(text("ru"), all::KOI8_R as EncodingRef),
+                                    (text("ja"), all::WINDOWS_31J),
+                                    (text("ja"), all::ISO_2022_JP),
+                                    (text("zh-hant"), all::BIG5_2003),
+                                    (text("pl"), all::UTF_8)] {
+            let input = encoding.encode(input, EncoderTrap::Strict).unwrap();
+            let mut whole = Detector::new();
+            whole.feed(&input);
+            for &size in &[1, 3, 7] {
+                let mut detector = Detector::new();
+                for chunk in input.chunks(size) {
+                    detector.feed(chunk);
+                }
+                let names = |d: &Detector| -> Vec<&str> {
+                    d.candidates().iter().map(|&(e, _)| e.name()).collect()
+                };
+                assert_eq!(names(&detector), names(&whole));
+            }
+        }
+    }
+
+    #[test]
+    fn test_candidates() {
+        let mut detector = Detector::new();
+        detector.feed(b"Hello, world!");
+        let candidates = detector.candidates();
+        assert_eq!(candidates.len(), CANDIDATES.len());
+        assert_eq!(candidates[0].0.name(), "windows-1252");
+        let sum: f64 = candidates.iter().map(|&(_, confidence)| confidence).sum();
+        assert!((sum - 1.0).abs() < 1e-9);
+
+        // more evidence makes the detector more confident.
+        let input = all::WINDOWS_31J.encode(text("ja"), EncoderTrap::Strict).unwrap();
+        let mut detector = Detector::new();
+        detector.feed(&input[..6]);
+        let confidence = detector.candidates()[0].1;
+        detector.feed(&input[6..]);
+        assert_eq!(detector.guess().name(), "windows-31j");
+        assert!(detector.candidates()[0].1 > confidence);
+        assert!(detector.candidates()[0].1 > 0.9);
+    }
+
+    #[test]
+    fn test_malformed() {
+        // invalid in every multibyte encoding, but windows-1252 makes sense of it.
+        let mut detector = Detector::new();
+        detector.feed(b"caf\xe9 cr\xe8me \xff");
+        assert_eq!(detector.guess().name(), "windows-1252");
+        let candidates = detector.candidates();
+        let utf8 = candidates.iter().find(|&&(e, _)| e.name() == "utf-8").unwrap();
+        assert!(utf8.1 < 1e-9);
+    }
+
+    #[test]
+    fn test_truncated() {
+        // valid UTF-8 up to a euro sign cut short at the end.
+        let input = b"caf\xc3\xa9 cr\xc3\xa8me \xe2\x82";
+        let mut detector = Detector::new();
+        detector.feed(input);
+        assert_eq!(detector.guess().name(), "utf-8");
+        detector.finish();
+        assert!(detector.guess().name() != "utf-8");
+        assert!(detect(input).name() != "utf-8");
+    }
+
+    #[bench]
+    fn bench_detect(bencher: &mut test::Bencher) {
+        let input = all::WINDOWS_31J.encode(testutils::JAPANESE_TEXT, EncoderTrap::Strict).unwrap();
+        bencher.bytes = input.len() as u64;
+        bencher.iter(|| test::black_box(detect(&input)))
+    }
+}
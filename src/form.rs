@@ -0,0 +1,248 @@
+// Copyright 2016 Mozilla Foundation. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE.txt or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Percent-encoding in legacy encodings for URLs and HTML form submission,
+//! as specified by the [WHATWG URL Standard](https://url.spec.whatwg.org/).
+
+use std::fmt::Write;
+use all;
+use compat::{from_encoding_rs, to_encoding_rs};
+use types::EncodingRef;
+
+/// The percent-encode sets of the URL Standard.
+/// Each set from `Query` on contains all bytes of the preceding one,
+/// except that `Path` does not contain `'` added by `SpecialQuery`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PercentEncodeSet {
+    /// The C0 control percent-encode set: C0 controls and all non-ASCII bytes.
+    C0Control,
+    /// The fragment percent-encode set: also adds space, `"`, `<`, `>` and `` ` ``.
+    Fragment,
+    /// The query percent-encode set: C0 control plus space, `"`, `#`, `<` and `>`.
+    Query,
+    /// The special-query percent-encode set: also adds `'`.
+    SpecialQuery,
+    /// The path percent-encode set: query plus `?`, `^`, `` ` ``, `{` and `}`.
+    Path,
+    /// The userinfo percent-encode set: also adds `/`, `:`, `;`, `=`, `@`,
+    /// `[` to `]` and `|`.
+    Userinfo,
+    /// The component percent-encode set: also adds `$` to `&`, `+` and `,`.
+    Component,
+    /// The `application/x-www-form-urlencoded` percent-encode set:
+    /// also adds `!`, `'` to `)` and `~`.
+    Form,
+}
+
+impl PercentEncodeSet {
+    /// Returns true if `byte` is percent-encoded by this set.
+    pub fn contains(&self, byte: u8) -> bool {
+        use self::PercentEncodeSet::*;
+        if byte < 0x20 || byte > 0x7e {
+            return true;
+        }
+        // the sets from `Query` on are nested; `Fragment` is not part of that chain.
+        let level = match *self {
+            C0Control | Fragment => 0,
+            Query => 1,
+            SpecialQuery => 2,
+            Path => 3,
+            Userinfo => 4,
+            Component => 5,
+            Form => 6,
+        };
+        match byte {
+            b' ' | b'"' | b'<' | b'>' => *self != C0Control,
+            b'`' => *self == Fragment || level >= 3,
+            b'#' => level >= 1,
+            b'\'' => *self == SpecialQuery || *self == Form,
+            b'?' | b'^' | b'{' | b'}' => level >= 3,
+            b'/' | b':' | b';' | b'=' | b'@' | b'[' | b'\\' | b']' | b'|' => level >= 4,
+            b'$' | b'%' | b'&' | b'+' | b',' => level >= 5,
+            b'!' | b'(' | b')' | b'~' => level >= 6,
+            _ => false,
+        }
+    }
+}
+
+/// Returns the encoding used for URLs and form submission in place of `encoding`,
+/// which is UTF-8 for the encodings that are not ASCII-compatible.
+/// Corresponds to WHATWG "get an output encoding".
+pub fn output_encoding(encoding: EncodingRef) -> EncodingRef {
+    match to_encoding_rs(encoding) {
+        Some(encoding) => from_encoding_rs(encoding.output_encoding()),
+        None if encoding.is_ascii_compatible() => encoding,
+        None => all::UTF_8,
+    }
+}
+
+/// Encodes `input` in the output encoding of `encoding` and appends it to `output`,
+/// percent-encoding the bytes in `set` and replacing unrepresentable characters
+/// with percent-encoded numeric character references (e.g. `%26%2312354%3B`).
+/// Spaces are written as `+` when `space_as_plus` is set.
+/// Corresponds to WHATWG "percent-encode after encoding".
+pub fn percent_encode_after_encoding(input: &str,
+                                     encoding: EncodingRef,
+                                     set: PercentEncodeSet,
+                                     space_as_plus: bool,
+                                     output: &mut String) {
+    let mut encoder = output_encoding(encoding).raw_encoder();
+    let mut bytes = Vec::new();
+    let mut remaining = 0;
+    loop {
+        let (offset, err) = encoder.raw_feed(&input[remaining..], &mut bytes);
+        percent_encode(&bytes, set, space_as_plus, output);
+        bytes.clear();
+        match err {
+            Some(err) => {
+                let unprocessed = remaining + offset;
+                remaining = (remaining as isize + err.upto) as usize;
+                for ch in input[unprocessed..remaining].chars() {
+                    write!(output, "%26%23{}%3B", ch as u32).unwrap();
+                }
+            }
+            None => break,
+        }
+    }
+    // the encoders of the output encodings do not fail at the end.
+    let _ = encoder.raw_finish(&mut bytes);
+    percent_encode(&bytes, set, space_as_plus, output);
+}
+
+fn percent_encode(bytes: &[u8], set: PercentEncodeSet, space_as_plus: bool, output: &mut String) {
+    for &byte in bytes {
+        if space_as_plus && byte == b' ' {
+            output.push('+');
+        } else if set.contains(byte) {
+            write!(output, "%{:02X}", byte).unwrap();
+        } else {
+            output.push(byte as char);
+        }
+    }
+}
+
+/// Serializes name-value pairs to an `application/x-www-form-urlencoded` string
+/// in the output encoding of `encoding`, e.g. for the query string of a legacy server.
+/// Corresponds to WHATWG "application/x-www-form-urlencoded serializer".
+pub fn form_urlencode<I, K, V>(input: I, encoding: EncodingRef) -> String
+    where I: IntoIterator<Item = (K, V)>,
+          K: AsRef<str>,
+          V: AsRef<str>
+{
+    let mut output = String::new();
+    for (name, value) in input {
+        if !output.is_empty() {
+            output.push('&');
+        }
+        percent_encode_after_encoding(name.as_ref(),
+                                      encoding,
+                                      PercentEncodeSet::Form,
+                                      true,
+                                      &mut output);
+        output.push('=');
+        percent_encode_after_encoding(value.as_ref(),
+                                      encoding,
+                                      PercentEncodeSet::Form,
+                                      true,
+                                      &mut output);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use all;
+    use testutils;
+
+    fn encode(input: &str, encoding: EncodingRef, set: PercentEncodeSet) -> String {
+        let mut output = String::new();
+        percent_encode_after_encoding(input, encoding, set, false, &mut output);
+        output
+    }
+
+    #[test]
+    fn test_percent_encode_sets() {
+        let ascii: String = (0x20u8..0x7f).map(|b| b as char).collect();
+        assert_eq!(encode(&ascii, all::UTF_8, PercentEncodeSet::C0Control), ascii);
+        assert_eq!(encode(&ascii, all::UTF_8, PercentEncodeSet::Fragment),
+                   "%20!%22#$%&'()*+,-./0123456789:;%3C=%3E?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_%60\
+                    abcdefghijklmnopqrstuvwxyz{|}~");
+        assert_eq!(encode(&ascii, all::UTF_8, PercentEncodeSet::Query),
+                   "%20!%22%23$%&'()*+,-./0123456789:;%3C=%3E?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`\
+                    abcdefghijklmnopqrstuvwxyz{|}~");
+        assert_eq!(encode(&ascii, all::UTF_8, PercentEncodeSet::SpecialQuery),
+                   "%20!%22%23$%&%27()*+,-./0123456789:;%3C=%3E?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`\
+                    abcdefghijklmnopqrstuvwxyz{|}~");
+        assert_eq!(encode(&ascii, all::UTF_8, PercentEncodeSet::Path),
+                   "%20!%22%23$%&'()*+,-./0123456789:;%3C=%3E%3F@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]\
+                    %5E_%60abcdefghijklmnopqrstuvwxyz%7B|%7D~");
+        assert_eq!(encode(&ascii, all::UTF_8, PercentEncodeSet::Userinfo),
+                   "%20!%22%23$%&'()*+,-.%2F0123456789%3A%3B%3C%3D%3E%3F%40\
+                    ABCDEFGHIJKLMNOPQRSTUVWXYZ%5B%5C%5D%5E_%60abcdefghijklmnopqrstuvwxyz\
+                    %7B%7C%7D~");
+        assert_eq!(encode(&ascii, all::UTF_8, PercentEncodeSet::Component),
+                   "%20!%22%23%24%25%26'()*%2B%2C-.%2F0123456789%3A%3B%3C%3D%3E%3F%40\
+                    ABCDEFGHIJKLMNOPQRSTUVWXYZ%5B%5C%5D%5E_%60abcdefghijklmnopqrstuvwxyz\
+                    %7B%7C%7D~");
+        assert_eq!(encode(&ascii, all::UTF_8, PercentEncodeSet::Form),
+                   "%20%21%22%23%24%25%26%27%28%29*%2B%2C-.%2F0123456789%3A%3B%3C%3D%3E%3F%40\
+                    ABCDEFGHIJKLMNOPQRSTUVWXYZ%5B%5C%5D%5E_%60abcdefghijklmnopqrstuvwxyz\
+                    %7B%7C%7D%7E");
+        assert_eq!(encode("\t\u{7f}", all::UTF_8, PercentEncodeSet::C0Control), "%09%7F");
+    }
+
+    #[test]
+    fn test_percent_encode_after_encoding() {
+        assert_eq!(encode("\u{c548}\u{b155} a", all::WINDOWS_949, PercentEncodeSet::Query),
+                   "%BE%C8%B3%E7%20a");
+        assert_eq!(encode("\u{3042}&", all::WINDOWS_1252, PercentEncodeSet::Query),
+                   "%26%2312354%3B&");
+        assert_eq!(encode("\u{3042}\u{c548}", all::WINDOWS_31J, PercentEncodeSet::Query),
+                   "%82%A0%26%2350504%3B");
+        // the escape sequences are percent-encoded as well.
+        assert_eq!(encode("\u{3042}\u{c548}", all::ISO_2022_JP, PercentEncodeSet::Query),
+                   "%1B$B$%22%1B(B%26%2350504%3B");
+        // UTF-16 and replacement are replaced with UTF-8.
+        assert_eq!(encode("\u{3042}", all::UTF_16LE, PercentEncodeSet::Query), "%E3%81%82");
+        assert_eq!(encode("\u{3042}", all::whatwg::REPLACEMENT, PercentEncodeSet::Query),
+                   "%E3%81%82");
+        // other encodings are kept even when they cannot represent much.
+        assert_eq!(encode("\u{3042}a", all::ERROR, PercentEncodeSet::Query), "%26%2312354%3Ba");
+    }
+
+    #[test]
+    fn test_output_encoding() {
+        assert_eq!(output_encoding(all::UTF_16BE).name(), "utf-8");
+        assert_eq!(output_encoding(all::whatwg::REPLACEMENT).name(), "utf-8");
+        assert_eq!(output_encoding(all::ISO_2022_JP).name(), "iso-2022-jp");
+        assert_eq!(output_encoding(all::ASCII).name(), "ascii");
+    }
+
+    #[test]
+    fn test_form_urlencode() {
+        assert_eq!(form_urlencode(vec![("q", "\u{c548}\u{b155} a+b"), ("lang", "ko")],
+                                  all::WINDOWS_949),
+                   "q=%BE%C8%B3%E7+a%2Bb&lang=ko");
+        assert_eq!(form_urlencode(vec![("\u{540d}\u{524d}", "\u{3042}\u{20ac}~")],
+                                  all::WINDOWS_31J),
+                   "%96%BC%91O=%82%A0%26%238364%3B%7E");
+        assert_eq!(form_urlencode(vec![(String::from("a"), "\u{3042}")], all::WINDOWS_1252),
+                   "a=%26%2312354%3B");
+        assert_eq!(form_urlencode(Vec::<(&str, &str)>::new(), all::UTF_8), "");
+    }
+
+    #[bench]
+    fn bench_form_urlencode(bencher: &mut test::Bencher) {
+        let s = testutils::JAPANESE_TEXT;
+        bencher.bytes = s.len() as u64;
+        bencher.iter(|| test::black_box(form_urlencode(vec![("text", s)], all::WINDOWS_31J)))
+    }
+}
@@ -0,0 +1,519 @@
+// Copyright 2016 Mozilla Foundation. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE.txt or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Adapters between `std::io` streams and the incremental interface.
+
+use std::{cmp, fmt, str};
+use std::io;
+use std::io::{BufRead, Read, Write};
+use types::{DecoderTrap, EncoderTrap, EncodingRef, RawDecoder, RawEncoder};
+
+/// The number of bytes `DecodeReader` reads from the inner reader at a time.
+const READ_CHUNK_LENGTH: usize = 8192;
+
+/// A reader that decodes the bytes of an inner reader and yields them as UTF-8.
+///
+/// Errors are handled by the given `DecoderTrap`. If the trap fails, reading
+/// returns an `io::Error` of the kind `InvalidData`. Byte sequences split
+/// across the reads from the inner reader are decoded as if the input were
+/// contiguous.
+pub struct DecodeReader<R> {
+    inner: R,
+    decoder: Box<RawDecoder>,
+    trap: DecoderTrap,
+    /// The bytes read from `inner` starting from the first unprocessed byte.
+    input: Vec<u8>,
+    /// The number of bytes at the start of `input` already fed to `decoder`.
+    fed: usize,
+    /// The decoded output not yet returned to the caller starts at `pos`.
+    output: String,
+    pos: usize,
+    finished: bool,
+}
+
+impl<R: Read> DecodeReader<R> {
+    /// Creates a reader that decodes `inner` in `encoding`.
+    pub fn new(inner: R, encoding: EncodingRef, trap: DecoderTrap) -> DecodeReader<R> {
+        DecodeReader {
+            inner: inner,
+            decoder: encoding.raw_decoder(),
+            trap: trap,
+            input: Vec::new(),
+            fed: 0,
+            output: String::new(),
+            pos: 0,
+            finished: false,
+        }
+    }
+
+    /// Returns a reference to the inner reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the inner reader.
+    ///
+    /// Reading from the inner reader directly skips those bytes in the decoded output.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwraps the inner reader, discarding any buffered input and output.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Decodes more input into `output`. May leave `output` empty.
+    fn decode_more(&mut self) -> io::Result<()> {
+        if self.fed == self.input.len() {
+            let len = self.input.len();
+            self.input.resize(len + READ_CHUNK_LENGTH, 0);
+            let read = match self.inner.read(&mut self.input[len..]) {
+                Ok(read) => read,
+                Err(e) => {
+                    self.input.truncate(len);
+                    return Err(e);
+                }
+            };
+            self.input.truncate(len + read);
+            if read == 0 {
+                return self.finish();
+            }
+        }
+
+        let (offset, err) = self.decoder.raw_feed(&self.input[self.fed..], &mut self.output);
+        // an offset of zero means that the unprocessed bytes may have started
+        // before this call, unless there were no such bytes.
+        let unprocessed = if offset > 0 || self.fed == 0 { self.fed + offset } else { 0 };
+        match err {
+            Some(err) => {
+                let remaining = (self.fed as isize + err.upto) as usize;
+                if !self.trap.trap(&mut *self.decoder,
+                                   &self.input[unprocessed..remaining],
+                                   &mut self.output) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, err.cause.into_owned()));
+                }
+                // the bytes starting at `remaining` are fed again.
+                self.input.drain(..remaining);
+                self.fed = 0;
+            }
+            None => {
+                self.input.drain(..unprocessed);
+                self.fed = self.input.len();
+            }
+        }
+        Ok(())
+    }
+
+    /// Finishes the decoder after the inner reader has reached its end.
+    fn finish(&mut self) -> io::Result<()> {
+        match self.decoder.raw_finish(&mut self.output) {
+            Some(err) => {
+                let remaining = (self.input.len() as isize + err.upto) as usize;
+                if !self.trap.trap(&mut *self.decoder, &self.input[..remaining], &mut self.output) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, err.cause.into_owned()));
+                }
+                // any bytes after the problem are fed again to a finished decoder.
+                self.input.drain(..remaining);
+                self.fed = 0;
+                self.finished = self.input.is_empty();
+            }
+            None => {
+                self.input.clear();
+                self.fed = 0;
+                self.finished = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecodeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = {
+            let available = self.fill_buf()?;
+            let len = cmp::min(available.len(), buf.len());
+            buf[..len].copy_from_slice(&available[..len]);
+            len
+        };
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: Read> BufRead for DecodeReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.output.len() && !self.finished {
+            self.output.clear();
+            self.pos = 0;
+            self.decode_more()?;
+        }
+        Ok(&self.output.as_bytes()[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = cmp::min(self.pos + amt, self.output.len());
+    }
+}
+
+/// A writer that accepts UTF-8 and writes it to an inner writer in another encoding.
+///
+/// Unmappable characters are handled by the given `EncoderTrap`. If the trap fails,
+/// or the input is not valid UTF-8, writing returns an `io::Error` of the kind
+/// `InvalidData`. As with other writers, the error is returned only when nothing could
+/// be written; otherwise `write` returns the number of bytes before the problem, and
+/// the next write fails. UTF-8 sequences split across the writes are encoded as if
+/// the input were contiguous.
+///
+/// The encoder state (e.g. the ISO-2022-JP shift back to ASCII) is flushed to the inner
+/// writer on `flush`, on `finish` and when the writer is dropped. Errors on drop are ignored;
+/// call `finish` to handle them.
+pub struct EncodeWriter<W: Write> {
+    /// `None` only after `finish`.
+    inner: Option<W>,
+    encoder: Box<RawEncoder>,
+    trap: EncoderTrap,
+    /// An incomplete UTF-8 sequence at the end of the last write.
+    partial: Vec<u8>,
+    /// The encoded output not yet written to `inner`.
+    output: Vec<u8>,
+}
+
+impl<W: Write> EncodeWriter<W> {
+    /// Creates a writer that encodes to `inner` in `encoding`.
+    pub fn new(inner: W, encoding: EncodingRef, trap: EncoderTrap) -> EncodeWriter<W> {
+        EncodeWriter {
+            inner: Some(inner),
+            encoder: encoding.raw_encoder(),
+            trap: trap,
+            partial: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Returns a mutable reference to the inner writer.
+    ///
+    /// Writing to the inner writer directly may interfere with the encoder state.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap()
+    }
+
+    /// Flushes the encoder state and returns the inner writer.
+    ///
+    /// Fails if the input ended in the middle of a UTF-8 sequence.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.partial.is_empty() {
+            self.partial.clear();
+            return Err(invalid_utf8());
+        }
+        self.finish_encoder()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    /// Encodes `input` to `output` and returns the number of bytes consumed.
+    ///
+    /// The encoding stops before the first character the trap rejects, and the error is
+    /// returned along with the number of bytes before that character. The character
+    /// is fed again on the next call.
+    fn encode(&mut self, input: &str) -> (usize, Option<io::Error>) {
+        let mut remaining = 0;
+        loop {
+            let (offset, err) = self.encoder.raw_feed(&input[remaining..], &mut self.output);
+            let unprocessed = remaining + offset;
+            let err = match err {
+                Some(err) => err,
+                None => return (input.len(), None),
+            };
+            let upto = (remaining as isize + err.upto) as usize;
+            let cause = match self.trap.try_trap(&mut *self.encoder,
+                                                 &input[unprocessed..upto],
+                                                 &mut self.output) {
+                Ok(true) => {
+                    remaining = upto;
+                    continue;
+                }
+                Ok(false) => err.cause,
+                Err(cause) => cause,
+            };
+            return (unprocessed,
+                    Some(io::Error::new(io::ErrorKind::InvalidData, cause.into_owned())));
+        }
+    }
+
+    /// Finishes the encoder, writes the result to `inner`
+    /// and replaces the encoder with a fresh one.
+    fn finish_encoder(&mut self) -> io::Result<()> {
+        let result = match self.encoder.raw_finish(&mut self.output) {
+            Some(err) => {
+                match self.trap.try_trap(&mut *self.encoder, "", &mut self.output) {
+                    Ok(true) => Ok(()),
+                    Ok(false) => {
+                        Err(io::Error::new(io::ErrorKind::InvalidData, err.cause.into_owned()))
+                    }
+                    Err(cause) => {
+                        Err(io::Error::new(io::ErrorKind::InvalidData, cause.into_owned()))
+                    }
+                }
+            }
+            None => Ok(()),
+        };
+        self.encoder = self.encoder.from_self();
+        self.write_output()?;
+        result
+    }
+
+    fn write_output(&mut self) -> io::Result<()> {
+        let result = self.inner.as_mut().unwrap().write_all(&self.output);
+        self.output.clear();
+        result
+    }
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8")
+}
+
+impl<W: Write> Write for EncodeWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut input = buf;
+        if !self.partial.is_empty() {
+            // complete the sequence from the last write first.
+            let partial_len = self.partial.len();
+            let take = cmp::min(4 - partial_len, buf.len());
+            self.partial.extend_from_slice(&buf[..take]);
+            let valid = match str::from_utf8(&self.partial) {
+                Ok(_) => self.partial.len(),
+                Err(e) => {
+                    if e.valid_up_to() == 0 {
+                        if e.error_len().is_some() {
+                            self.partial.truncate(partial_len);
+                            return Err(invalid_utf8());
+                        }
+                        // still incomplete
+                        return Ok(buf.len());
+                    }
+                    e.valid_up_to()
+                }
+            };
+            let partial = ::std::mem::replace(&mut self.partial, Vec::new());
+            let completed = unsafe { str::from_utf8_unchecked(&partial[..valid]) };
+            let (consumed, err) = self.encode(completed);
+            self.write_output()?;
+            if consumed < valid {
+                if consumed == 0 {
+                    // keep the sequence from the last write for the next call.
+                    self.partial = partial;
+                    self.partial.truncate(partial_len);
+                    return Err(err.unwrap());
+                }
+                return Ok(consumed - partial_len);
+            }
+            input = &buf[valid - partial_len..];
+        }
+
+        let (valid, error_len) = match str::from_utf8(input) {
+            Ok(_) => (input.len(), None),
+            Err(e) => (e.valid_up_to(), e.error_len()),
+        };
+        let (consumed, err) = self.encode(unsafe { str::from_utf8_unchecked(&input[..valid]) });
+        self.write_output()?;
+        if consumed < valid || error_len.is_some() {
+            // report the error on the next write unless nothing was written.
+            let written = buf.len() - input.len() + consumed;
+            return if written == 0 { Err(err.unwrap_or_else(invalid_utf8)) } else { Ok(written) };
+        }
+        self.partial.extend_from_slice(&input[valid..]);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.finish_encoder()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> fmt::Write for EncodeWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if !self.partial.is_empty() {
+            return Err(fmt::Error);
+        }
+        let (consumed, _) = self.encode(s);
+        self.write_output().map_err(|_| fmt::Error)?;
+        if consumed < s.len() { Err(fmt::Error) } else { Ok(()) }
+    }
+}
+
+impl<W: Write> Drop for EncodeWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.finish_encoder();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::{BufRead, Read, Write};
+    use super::*;
+    use all;
+    use testutils;
+    use types::*;
+
+    /// A reader that returns at most one byte per `read` call.
+    struct ByteByByte<'a>(&'a [u8]);
+
+    impl<'a> Read for ByteByByte<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    fn read_all<R: Read>(mut reader: R) -> io::Result<String> {
+        let mut ret = String::new();
+        reader.read_to_string(&mut ret).map(|_| ret)
+    }
+
+    #[test]
+    fn test_decode_reader() {
+        for &encoding in &[all::UTF_8 as EncodingRef, all::WINDOWS_31J, all::EUC_JP,
+                           all::ISO_2022_JP, all::GB18030, all::UTF_16LE, all::UTF_16BE] {
+            let input = encoding.encode(testutils::JAPANESE_TEXT, EncoderTrap::Strict).unwrap();
+            let reader = DecodeReader::new(&input[..], encoding, DecoderTrap::Strict);
+            assert_eq!(read_all(reader).unwrap(), testutils::JAPANESE_TEXT);
+            let reader = DecodeReader::new(ByteByByte(&input), encoding, DecoderTrap::Strict);
+            assert_eq!(read_all(reader).unwrap(), testutils::JAPANESE_TEXT);
+        }
+    }
+
+    #[test]
+    fn test_decode_reader_trap() {
+        let input = b"\xe3\x81\x82\xff\xe3\x81\x84\xe3\x81";
+        for &(trap, expected) in &[(DecoderTrap::Replace, "\u{3042}\u{fffd}\u{3044}\u{fffd}"),
+                                   (DecoderTrap::Ignore, "\u{3042}\u{3044}")] {
+            let reader = DecodeReader::new(&input[..], all::UTF_8, trap);
+            assert_eq!(read_all(reader).unwrap(), expected);
+            let reader = DecodeReader::new(ByteByByte(input), all::UTF_8, trap);
+            assert_eq!(read_all(reader).unwrap(), expected);
+        }
+
+        let reader = DecodeReader::new(ByteByByte(input), all::UTF_8, DecoderTrap::Strict);
+        assert_eq!(read_all(reader).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_decode_reader_trap_input() {
+        // the trap sees the problematic bytes even when they are split across reads
+        fn check(_: &mut RawDecoder, input: &[u8], output: &mut StringWriter) -> bool {
+            output.write_str(&format!("[{:?}]", input));
+            true
+        }
+        let input = b"\x82\xa0\x82\x7f\x82";
+        let reader = DecodeReader::new(ByteByByte(input), all::WINDOWS_31J, DecoderTrap::Call(check));
+        assert_eq!(read_all(reader).unwrap(), "\u{3042}[[130]]\x7f[[130]]");
+    }
+
+    #[test]
+    fn test_decode_reader_buf_read() {
+        let input = all::WINDOWS_1252.encode("caf\u{e9}\nna\u{ef}ve\n", EncoderTrap::Strict).unwrap();
+        let reader = DecodeReader::new(ByteByByte(&input), all::WINDOWS_1252, DecoderTrap::Strict);
+        let lines: Vec<String> = reader.lines().map(|line| line.unwrap()).collect();
+        assert_eq!(lines, ["caf\u{e9}", "na\u{ef}ve"]);
+    }
+
+    #[test]
+    fn test_encode_writer() {
+        for &encoding in &[all::UTF_8 as EncodingRef, all::WINDOWS_31J, all::EUC_JP,
+                           all::GB18030, all::GBK, all::UTF_16LE, all::UTF_16BE] {
+            let expected = encoding.encode(testutils::JAPANESE_TEXT, EncoderTrap::Strict).unwrap();
+            let mut writer = EncodeWriter::new(Vec::new(), encoding, EncoderTrap::Strict);
+            writer.write_all(testutils::JAPANESE_TEXT.as_bytes()).unwrap();
+            assert_eq!(writer.finish().unwrap(), expected);
+
+            // UTF-8 sequences split across writes
+            let mut writer = EncodeWriter::new(Vec::new(), encoding, EncoderTrap::Strict);
+            for b in testutils::JAPANESE_TEXT.as_bytes() {
+                assert_eq!(writer.write(&[*b]).unwrap(), 1);
+            }
+            assert_eq!(writer.finish().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_encode_writer_flushes_state() {
+        let mut output = Vec::new();
+        {
+            let mut writer = EncodeWriter::new(&mut output, all::ISO_2022_JP, EncoderTrap::Strict);
+            writer.write_all("A\u{3042}".as_bytes()).unwrap();
+            writer.flush().unwrap();
+            assert_eq!(*writer.get_ref(), b"A\x1b$B$\"\x1b(B");
+            writer.write_all("\u{3044}".as_bytes()).unwrap();
+        }
+        // dropping the writer returns to ASCII as well
+        assert_eq!(output, b"A\x1b$B$\"\x1b(B\x1b$B$$\x1b(B");
+    }
+
+    #[test]
+    fn test_encode_writer_trap() {
+        let mut writer = EncodeWriter::new(Vec::new(), all::WINDOWS_1252, EncoderTrap::NcrEscape);
+        writer.write_all("caf\u{e9} \u{2603}".as_bytes()).unwrap();
+        assert_eq!(writer.finish().unwrap(), b"caf\xe9 &#9731;");
+
+        let mut writer = EncodeWriter::new(Vec::new(), all::WINDOWS_1252, EncoderTrap::Strict);
+        let err = writer.write_all("caf\u{e9} \u{2603}".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(writer.get_ref(), b"caf\xe9 ");
+
+        // the unmappable character is not consumed and the error comes on the next write
+        let mut writer = EncodeWriter::new(Vec::new(), all::WINDOWS_1252, EncoderTrap::Strict);
+        assert_eq!(writer.write("caf\u{e9} \u{2603}!".as_bytes()).unwrap(), 6);
+        assert_eq!(writer.get_ref(), b"caf\xe9 ");
+        let err = writer.write("\u{2603}!".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(writer.get_ref(), b"caf\xe9 ");
+        assert_eq!(writer.write(b"!").unwrap(), 1);
+        assert_eq!(writer.finish().unwrap(), b"caf\xe9 !");
+
+        // the same for a character completing a sequence split across writes
+        let mut writer = EncodeWriter::new(Vec::new(), all::WINDOWS_1252, EncoderTrap::Strict);
+        assert_eq!(writer.write(b"a\xe2\x98").unwrap(), 3);
+        assert_eq!(writer.write(b"\x83b").unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(writer.get_ref(), b"a");
+        assert_eq!(writer.write(b"\x83b").unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_encode_writer_invalid_utf8() {
+        let mut writer = EncodeWriter::new(Vec::new(), all::WINDOWS_1252, EncoderTrap::Strict);
+        assert_eq!(writer.write(b"ab\xffc").unwrap(), 2);
+        assert_eq!(writer.write(b"\xffc").unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut writer = EncodeWriter::new(Vec::new(), all::WINDOWS_1252, EncoderTrap::Strict);
+        writer.write_all(b"caf\xc3").unwrap();
+        assert_eq!(writer.finish().unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_encode_writer_fmt() {
+        use std::fmt;
+        let mut writer = EncodeWriter::new(Vec::new(), all::WINDOWS_31J, EncoderTrap::Strict);
+        fmt::Write::write_fmt(&mut writer, format_args!("{}{}", "\u{65e5}\u{672c}", 1)).unwrap();
+        assert_eq!(writer.finish().unwrap(), b"\x93\xfa\x96\x7b1");
+    }
+}
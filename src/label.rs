@@ -21,7 +21,7 @@ pub fn encoding_from_windows_code_page(cp: usize) -> Option<EncodingRef> {
     match cp {
         65001 => Some(all::UTF_8 as EncodingRef),
         866 => Some(all::IBM866 as EncodingRef),
-        // 28591 => Some(all::ISO_8859_1 as EncodingRef),
+        28591 => Some(all::ISO_8859_1 as EncodingRef),
         28592 => Some(all::ISO_8859_2 as EncodingRef),
         28593 => Some(all::ISO_8859_3 as EncodingRef),
         28594 => Some(all::ISO_8859_4 as EncodingRef),
@@ -47,6 +47,7 @@ pub fn encoding_from_windows_code_page(cp: usize) -> Option<EncodingRef> {
         1258 => Some(all::WINDOWS_1258 as EncodingRef),
         1259 => Some(all::MAC_CYRILLIC as EncodingRef),
         936 | 54936 => Some(all::GB18030 as EncodingRef), // XXX technically wrong
+        52936 => Some(all::HZ as EncodingRef),
         950 => Some(all::BIG5_2003 as EncodingRef),
         20932 => Some(all::EUC_JP as EncodingRef),
         50220 => Some(all::ISO_2022_JP as EncodingRef),
@@ -58,11 +59,157 @@ pub fn encoding_from_windows_code_page(cp: usize) -> Option<EncodingRef> {
     }
 }
 
+/// Returns an encoding from the `charset` parameter of a `Content-Type` header value, if any.
+/// Several header values may be combined with commas, in which case the last MIME type
+/// counts and keeps the charset of a preceding one of the same type unless it has its own.
+/// Implements the charset part of "extract a MIME type" and "parse a MIME type":
+/// https://fetch.spec.whatwg.org/#concept-header-extract-mime-type
+/// https://mimesniff.spec.whatwg.org/#parse-a-mime-type
+pub fn encoding_from_content_type(content_type: &str) -> Option<EncodingRef> {
+    let mut essence = None;
+    // the charset of the MIME type that introduced the current essence.
+    let mut essence_charset = None;
+    let mut charset = None;
+    for value in split_header_value(content_type) {
+        let (value_essence, value_charset) = match parse_mime_type(value) {
+            Some((ref essence, _)) if essence == "*/*" => continue,
+            Some(parsed) => parsed,
+            None => continue,
+        };
+        if essence.as_ref() != Some(&value_essence) {
+            essence = Some(value_essence);
+            essence_charset = value_charset.clone();
+            charset = value_charset;
+        } else {
+            charset = value_charset.or_else(|| essence_charset.clone());
+        }
+    }
+    charset.and_then(|charset| encoding_from_whatwg_label(&charset))
+}
+
+fn is_http_whitespace(c: char) -> bool {
+    c == '\t' || c == '\n' || c == '\r' || c == ' '
+}
+
+fn is_http_token(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
+fn is_http_quoted_string_token(c: char) -> bool {
+    c == '\t' || (c >= ' ' && c <= '~') || (c >= '\u{80}' && c <= '\u{ff}')
+}
+
+/// Returns the unescaped value of the quoted string starting at `input[*pos]`
+/// and moves `pos` past its closing quote.
+/// Implements "collect an HTTP quoted string".
+fn collect_quoted_string(input: &str, pos: &mut usize) -> String {
+    let start = *pos + 1;
+    let mut value = String::new();
+    let mut escaped = false;
+    *pos = input.len();
+    for (i, c) in input[start..].char_indices() {
+        if escaped {
+            value.push(c);
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            *pos = start + i + 1;
+            return value;
+        } else {
+            value.push(c);
+        }
+    }
+    if escaped {
+        value.push('\\');
+    }
+    value
+}
+
+/// Splits a header value at the commas outside quoted strings.
+/// Implements the splitting part of "get, decode, and split".
+fn split_header_value(input: &str) -> Vec<&str> {
+    let mut values = Vec::new();
+    let mut start = 0;
+    let mut pos = 0;
+    loop {
+        pos = input[pos..].find(|c| c == '"' || c == ',').map_or(input.len(), |i| pos + i);
+        if input[pos..].starts_with('"') {
+            collect_quoted_string(input, &mut pos);
+            if pos < input.len() {
+                continue;
+            }
+        }
+        values.push(input[start..pos].trim_matches(|c| c == '\t' || c == ' '));
+        if pos >= input.len() {
+            return values;
+        }
+        pos += 1;
+        start = pos;
+    }
+}
+
+/// Returns the lowercased essence (`type/subtype`) of a MIME type and its `charset` parameter.
+/// Implements "parse a MIME type" but ignores the other parameters.
+fn parse_mime_type(input: &str) -> Option<(String, Option<String>)> {
+    let input = input.trim_matches(is_http_whitespace);
+    let slash = input.find('/')?;
+    let rest = &input[slash + 1..];
+    let subtype_end = rest.find(';').unwrap_or(rest.len());
+    let type_ = &input[..slash];
+    let subtype = rest[..subtype_end].trim_end_matches(is_http_whitespace);
+    if !is_http_token(type_) || !is_http_token(subtype) {
+        return None;
+    }
+    let essence = format!("{}/{}", type_, subtype).to_ascii_lowercase();
+
+    let mut charset = None;
+    let mut pos = slash + 1 + subtype_end;
+    while pos < input.len() {
+        // skips `;` and the whitespace after it.
+        pos += 1;
+        pos = input.len() - input[pos..].trim_start_matches(is_http_whitespace).len();
+        let name_end = input[pos..].find(|c| c == ';' || c == '=').map_or(input.len(), |i| pos + i);
+        let name = input[pos..name_end].to_ascii_lowercase();
+        pos = name_end;
+        if input[pos..].starts_with(';') {
+            continue;
+        }
+        // skips `=`.
+        pos += 1;
+        if pos >= input.len() {
+            break;
+        }
+        let value = if input[pos..].starts_with('"') {
+            let value = collect_quoted_string(input, &mut pos);
+            pos = input[pos..].find(';').map_or(input.len(), |i| pos + i);
+            value
+        } else {
+            let end = input[pos..].find(';').map_or(input.len(), |i| pos + i);
+            let value = input[pos..end].trim_end_matches(is_http_whitespace);
+            pos = end;
+            if value.is_empty() {
+                continue;
+            }
+            value.to_owned()
+        };
+        // only the first valid parameter of a name counts.
+        if name == "charset" && charset.is_none() &&
+           value.chars().all(is_http_quoted_string_token) {
+            charset = Some(value);
+        }
+    }
+    Some((essence, charset))
+}
+
 #[cfg(test)]
 mod tests {
     extern crate test;
+    extern crate encoding_rs;
     use all;
-    use super::encoding_from_whatwg_label;
+    use super::{encoding_from_whatwg_label, encoding_from_content_type};
+    use types::Encoding;
+    use compat::{from_encoding_rs, to_encoding_rs};
 
     #[test]
     fn test_encoding_from_whatwg_label() {
@@ -87,6 +234,60 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_hz_is_not_a_whatwg_label() {
+        // HZ is available from `all::HZ` but the WHATWG label maps to the replacement encoding.
+        assert_eq!(encoding_from_whatwg_label("hz-gb-2312").map(|e| e.name()),
+                   Some("encoder-only-utf-8"));
+        assert_eq!(all::HZ.whatwg_name(), None);
+    }
+
+    #[test]
+    fn test_encoding_from_content_type() {
+        fn charset(content_type: &str) -> Option<&'static str> {
+            encoding_from_content_type(content_type).and_then(|e| e.whatwg_name())
+        }
+        assert_eq!(charset("text/html; charset=ISO-8859-4"), Some("iso-8859-4"));
+        assert_eq!(charset("TEXT/HTML;CHARSET=Shift_JIS"), Some("shift_jis"));
+        assert_eq!(charset(" text/html ;\tcharset=gbk ; q=1 "), Some("gbk"));
+        assert_eq!(charset("text/html; charset= gbk"), Some("gbk"));
+        assert_eq!(charset("text/html"), None);
+        assert_eq!(charset("text/html; charset=bogus"), None);
+        assert_eq!(charset("charset=gbk"), None);
+        assert_eq!(charset("text/; charset=gbk"), None);
+        assert_eq!(charset("text html/plain; charset=gbk"), None);
+        assert_eq!(charset(""), None);
+
+        // quoted strings.
+        assert_eq!(charset("text/html; charset=\"gbk\""), Some("gbk"));
+        assert_eq!(charset("text/html; charset=\"\\g\\b\\k\""), Some("gbk"));
+        assert_eq!(charset("text/html; charset=\"gbk\" trailing; x=y"), Some("gbk"));
+        assert_eq!(charset("text/html; x=\"a;charset=gbk\"; charset=big5"), Some("big5"));
+        assert_eq!(charset("text/html; charset=\"gbk"), Some("gbk"));
+        assert_eq!(charset("text/html; charset=\"gbk\u{100}\""), None);
+
+        // parameters.
+        assert_eq!(charset("text/html; charset=gbk; charset=big5"), Some("gbk"));
+        assert_eq!(charset("text/html; charset=; charset=gbk"), Some("gbk"));
+        assert_eq!(charset("text/html; charset; charset=gbk"), Some("gbk"));
+        assert_eq!(charset("text/html; charset=\"\u{e9}\"; charset=gbk"), None);
+        assert_eq!(charset("text/html; charset =gbk"), None);
+        assert_eq!(charset("text/html; charset"), None);
+        assert_eq!(charset("text/html; charset="), None);
+
+        // combined header values.
+        assert_eq!(charset("text/html; charset=gbk, text/html; charset=big5"), Some("big5"));
+        assert_eq!(charset("text/html; charset=gbk, text/html"), Some("gbk"));
+        assert_eq!(charset("text/html; charset=gbk, text/html; charset=big5, text/html"),
+                   Some("gbk"));
+        assert_eq!(charset("text/html; charset=gbk, text/plain"), None);
+        assert_eq!(charset("text/html; charset=gbk, text/plain, text/html"), None);
+        assert_eq!(charset("text/html; charset=gbk, */*"), Some("gbk"));
+        assert_eq!(charset("text/html; charset=gbk, bogus"), Some("gbk"));
+        assert_eq!(charset("text/html; charset=\"gbk\", text/html; x=\"a,b\""), Some("gbk"));
+        assert_eq!(charset("text/html; x=\"a, text/plain\"; charset=gbk"), Some("gbk"));
+    }
+
     #[bench]
     fn bench_encoding_from_whatwg_label(bencher: &mut test::Bencher) {
         bencher.iter(|| {
@@ -95,4 +296,25 @@ mod tests {
             })
         })
     }
+
+    // `REPLACEMENT` is the last in the order the lookup used to search in.
+
+    #[bench]
+    fn bench_from_encoding_rs(bencher: &mut test::Bencher) {
+        bencher.iter(|| {
+            test::black_box({
+                from_encoding_rs(test::black_box(encoding_rs::REPLACEMENT))
+            })
+        })
+    }
+
+    #[bench]
+    fn bench_to_encoding_rs(bencher: &mut test::Bencher) {
+        let encoding = from_encoding_rs(encoding_rs::REPLACEMENT);
+        bencher.iter(|| {
+            test::black_box({
+                to_encoding_rs(test::black_box(encoding))
+            })
+        })
+    }
 }
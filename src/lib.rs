@@ -96,6 +96,33 @@
 //! assert_eq!(chars, "Acme\u{fffd}");
 //! ~~~~
 //!
+//! To decode a stream without reading it into memory first:
+//!
+//! ~~~~ {.rust}
+//! use std::io::BufRead;
+//! use encoding::DecoderTrap;
+//! use encoding::io::DecodeReader;
+//! use encoding::all::WINDOWS_1252;
+//!
+//! let log: &[u8] = &[99,97,102,233,10,110,97,239,118,101,10];
+//! let reader = DecodeReader::new(log, WINDOWS_1252, DecoderTrap::Replace);
+//! let lines: Vec<String> = reader.lines().map(|line| line.unwrap()).collect();
+//! assert_eq!(lines, ["caf\u{e9}", "na\u{ef}ve"]);
+//! ~~~~
+//!
+//! To encode a stream without keeping the whole output in memory:
+//!
+//! ~~~~ {.rust}
+//! use std::io::Write;
+//! use encoding::EncoderTrap;
+//! use encoding::io::EncodeWriter;
+//! use encoding::all::ISO_2022_JP;
+//!
+//! let mut writer = EncodeWriter::new(Vec::new(), ISO_2022_JP, EncoderTrap::Strict);
+//! writer.write_all("\u{3042}".as_bytes()).unwrap();
+//! assert_eq!(writer.finish().unwrap(), [27,36,66,36,34,27,40,66]);
+//! ~~~~
+//!
 //! A practical example of custom encoder traps:
 //!
 //! ~~~~ {.rust}
@@ -214,7 +241,9 @@
 #![cfg_attr(test, feature(test))] // lib stability features as per RFC #507
 
 pub use self::types::{CodecError, ByteWriter, StringWriter, RawEncoder, RawDecoder, EncodingRef,
-                      Encoding, EncoderTrapFunc, DecoderTrapFunc, DecoderTrap, EncoderTrap, decode}; // reexport
+                      Encoding, EncoderTrapFunc, DecoderTrapFunc, DecoderTrap, EncoderTrap, decode,
+                      decode_cow, BomSniffingDecoder, EncodingRsMapping, EncodingError,
+                      EncodingErrorKind}; // reexport
 
 #[macro_use]mod util;
 #[cfg(test)] #[macro_use]mod testutils;
@@ -224,6 +253,7 @@ pub mod types;
 /// Codec implementations.
 mod codec {
     pub mod ascii;
+    pub mod error;
     pub mod singlebyte;
     pub mod utf_8;
     pub mod utf_16;
@@ -235,9 +265,15 @@ mod codec {
 
 pub mod all;
 pub mod label;
+pub mod io;
+pub mod form;
+pub mod detect;
+pub mod sniff;
+pub mod mime;
 
 mod compat;
-pub use self::compat::{from_encoding_rs, to_encoding_rs};
+pub use self::compat::{from_encoding_rs, to_encoding_rs, to_encoding_rs_mapping,
+                       NoEncodingRsCounterpart};
 
 #[cfg(test)]
 mod tests {
@@ -263,4 +299,133 @@ mod tests {
         test_one(&[0xFF, 0xFE, 0xE9, 0x00], "é", "utf-16le");
         test_one(&[0xE9, 0x00], "é\x00", "windows-1252");
     }
+
+    #[test]
+    fn test_encoder_traps_never_panic() {
+        use std::io::Write;
+        let input = "A\u{3042}\u{e9}\u{10ffff}\u{1a}";
+        for &encoding in all::encodings() {
+            for &trap in &[EncoderTrap::Replace, EncoderTrap::NcrEscape] {
+                assert!(encoding.encode(input, trap).is_ok(), "{}", encoding.name());
+                assert!(encoding.encode_cow(input, trap).is_ok(), "{}", encoding.name());
+                let mut output = Vec::new();
+                assert!(encoding.encode_to(input, trap, &mut output).is_ok(),
+                        "{}", encoding.name());
+                assert!(encoding.encode_checked(input, trap).is_ok(), "{}", encoding.name());
+                let mut writer = io::EncodeWriter::new(Vec::new(), encoding, trap);
+                assert!(writer.write_all(input.as_bytes()).is_ok(), "{}", encoding.name());
+            }
+            for &replacement in &["\u{3013}", "\u{10ffff}"] {
+                let expected = if encoding.can_encode(replacement) || encoding.can_encode(input) {
+                    Ok(())
+                } else {
+                    Err(::std::borrow::Cow::from("cannot reencode a replacement string"))
+                };
+                assert_eq!(encoding.encode_replacing(input, replacement).map(|_| ()), expected,
+                           "{}", encoding.name());
+                let mut output = Vec::new();
+                assert_eq!(encoding.encode_to_replacing(input, replacement, &mut output), expected,
+                           "{}", encoding.name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_cow() {
+        use std::borrow::Cow;
+
+        fn test_one(input: &[u8], expected_result: &str, expected_encoding: &str,
+                    expected_bom: bool, expected_borrowed: bool) {
+            let (result, used_encoding, bom) = decode_cow(input,
+                                                          DecoderTrap::Strict,
+                                                          all::UTF_8 as EncodingRef);
+            let result = result.unwrap();
+            assert_eq!(used_encoding.name(), expected_encoding);
+            assert_eq!(bom, expected_bom);
+            assert_eq!(&result[..], expected_result);
+            match result {
+                Cow::Borrowed(_) => assert!(expected_borrowed),
+                Cow::Owned(_) => assert!(!expected_borrowed),
+            }
+        }
+
+        test_one(&[0xEF, 0xBB, 0xBF, 0xC3, 0xA9], "é", "utf-8", true, true);
+        test_one(&[0xC3, 0xA9], "é", "utf-8", false, true);
+        assert!(decode_cow(&[0xEF, 0xBB], DecoderTrap::Strict, all::UTF_8).0.is_err());
+
+        test_one(&[0xFE, 0xFF, 0x00, 0xE9], "é", "utf-16be", true, false);
+        test_one(&[0xFF, 0xFE, 0xE9, 0x00], "é", "utf-16le", true, false);
+
+        let (result, used_encoding, bom) = decode_cow(&[0xEF, 0xBB, 0xBF, 0xC3, 0xA9],
+                                                      DecoderTrap::Strict,
+                                                      all::WINDOWS_1252 as EncodingRef);
+        assert_eq!(result, Ok(Cow::Borrowed("é")));
+        assert_eq!(used_encoding.name(), "utf-8");
+        assert!(bom);
+        let (result, used_encoding, bom) = decode_cow(&[0xC3, 0xA9],
+                                                      DecoderTrap::Strict,
+                                                      all::WINDOWS_1252 as EncodingRef);
+        assert_eq!(result, Ok(Cow::Owned::<str>("Ã©".to_string())));
+        assert_eq!(used_encoding.name(), "windows-1252");
+        assert!(!bom);
+    }
+
+    #[test]
+    fn test_bom_sniffing_decoder_split_bom() {
+        let mut d = BomSniffingDecoder::new(all::WINDOWS_1252);
+        assert_eq!(d.encoding().map(|e| e.name()), None);
+        assert_eq!(d.has_bom(), None);
+        {
+            let d: &mut RawDecoder = &mut d;
+            assert_feed_ok!(d, [], [0xEF], "");
+            assert_feed_ok!(d, [], [0xBB], "");
+            assert_feed_ok!(d, [0xBF, 0xC3, 0xA9], [], "é");
+            assert_finish_ok!(d, "");
+        }
+        assert_eq!(d.encoding().map(|e| e.name()), Some("utf-8"));
+        assert_eq!(d.has_bom(), Some(true));
+
+        let mut d = BomSniffingDecoder::new(all::WINDOWS_1252);
+        {
+            let d: &mut RawDecoder = &mut d;
+            assert_feed_ok!(d, [], [0xFE], "");
+            assert_feed_ok!(d, [0xFF], [0x00], "");
+            assert_feed_ok!(d, [0xE9], [], "é");
+            assert_finish_ok!(d, "");
+        }
+        assert_eq!(d.encoding().map(|e| e.name()), Some("utf-16be"));
+        assert_eq!(d.has_bom(), Some(true));
+    }
+
+    #[test]
+    fn test_bom_sniffing_decoder_no_bom() {
+        let mut d = BomSniffingDecoder::new(all::WINDOWS_1252);
+        {
+            let d: &mut RawDecoder = &mut d;
+            assert_feed_ok!(d, [], [0xEF], "");
+            assert_feed_ok!(d, [0x41], [], "ïA");
+            assert_feed_ok!(d, [0xFE, 0xFF], [], "þÿ");
+            assert_finish_ok!(d, "");
+        }
+        assert_eq!(d.encoding().map(|e| e.name()), Some("windows-1252"));
+        assert_eq!(d.has_bom(), Some(false));
+
+        let mut d = BomSniffingDecoder::new(all::UTF_8);
+        {
+            let d: &mut RawDecoder = &mut d;
+            assert_feed_ok!(d, [], [0xEF, 0xBB], "");
+            assert_feed_err!(d, -2, [], [0xEF, 0xBB], [0x41], "");
+            assert_feed_ok!(d, [0x41], [], "A");
+            assert_finish_ok!(d, "");
+        }
+        assert_eq!(d.has_bom(), Some(false));
+
+        let mut d = BomSniffingDecoder::new(all::UTF_8);
+        {
+            let d: &mut RawDecoder = &mut d;
+            assert_feed_ok!(d, [], [0xEF, 0xBB], "");
+            assert_finish_err!(d, "");
+        }
+        assert_eq!(d.has_bom(), Some(false));
+    }
 }
@@ -0,0 +1,419 @@
+// Copyright 2016 Mozilla Foundation. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE.txt or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! MIME encoded-words, which carry non-ASCII text in mail headers as in
+//! `=?iso-2022-jp?B?GyRCJDMkcyRLJEEkTxsoQg==?=` ([RFC 2047]),
+//! and the extended parameter values of [RFC 2231].
+//!
+//! [RFC 2047]: https://tools.ietf.org/html/rfc2047
+//! [RFC 2231]: https://tools.ietf.org/html/rfc2231
+
+use label::encoding_from_whatwg_label;
+use types::{DecoderTrap, EncoderTrap, EncodingError, EncodingErrorKind, EncodingRef};
+
+/// The maximum length of an encoded-word, including its delimiters.
+const MAX_ENCODED_WORD_LEN: usize = 75;
+
+const BASE64: &'static [u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn is_whitespace(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\r' || c == '\n'
+}
+
+/// Returns true for the bytes written as themselves in the Q encoding,
+/// which are safe in every part of a header.
+fn is_q_literal(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!*+-/".contains(&byte)
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|value| value as u8)
+}
+
+/// Decodes the headers of a mail message, replacing its encoded-words with their text.
+/// Whitespace between encoded-words is dropped, and adjacent encoded-words in the same
+/// ASCII-compatible charset are decoded together, so characters split across them survive.
+/// Encoded-words that are malformed or in an unknown charset are kept as they are.
+/// The charset is looked up with `label::encoding_from_whatwg_label`,
+/// ignoring the language of RFC 2231 (as in `=?utf-8*en?Q?...?=`).
+pub fn decode_header(input: &str) -> String {
+    let mut output = String::new();
+    let mut pending: Option<(EncodingRef, Vec<u8>)> = None;
+    let mut text_start = 0;
+    let mut pos = 0;
+    while let Some(i) = input[pos..].find("=?") {
+        pos += i;
+        let (encoding, bytes, len) = match parse_encoded_word(&input[pos..]) {
+            Some(word) => word,
+            None => {
+                pos += 1;
+                continue;
+            }
+        };
+        let between = &input[text_start..pos];
+        if pending.is_none() || !between.chars().all(is_whitespace) {
+            flush(&mut pending, &mut output);
+            output.push_str(between);
+        }
+        match pending {
+            // the escape sequences of ISO-2022-JP are not meant to follow each other.
+            Some((pending_encoding, ref mut pending_bytes))
+                if pending_encoding.name() == encoding.name() &&
+                   encoding.is_ascii_compatible() => {
+                pending_bytes.extend_from_slice(&bytes)
+            }
+            _ => {
+                flush(&mut pending, &mut output);
+                pending = Some((encoding, bytes));
+            }
+        }
+        pos += len;
+        text_start = pos;
+    }
+    flush(&mut pending, &mut output);
+    output.push_str(&input[text_start..]);
+    output
+}
+
+fn flush(pending: &mut Option<(EncodingRef, Vec<u8>)>, output: &mut String) {
+    if let Some((encoding, bytes)) = pending.take() {
+        // cannot fail with `DecoderTrap::Replace`.
+        let _ = encoding.decode_to(&bytes, DecoderTrap::Replace, output);
+    }
+}
+
+/// Parses the encoded-word at the start of `input`
+/// and returns its encoding, decoded bytes and length.
+fn parse_encoded_word(input: &str) -> Option<(EncodingRef, Vec<u8>, usize)> {
+    let mut parts = input[2..].splitn(4, '?');
+    let charset = parts.next()?;
+    let method = parts.next()?;
+    let text = parts.next()?;
+    if !parts.next()?.starts_with('=') {
+        return None;
+    }
+    if charset.is_empty() || !charset.bytes().all(|byte| byte > b' ' && byte < 0x7f) ||
+       !text.bytes().all(|byte| byte > b' ' && byte < 0x7f) {
+        return None;
+    }
+    let len = charset.len() + method.len() + text.len() + 6;
+    let encoding = encoding_from_whatwg_label(charset.split('*').next().unwrap())?;
+    let bytes = match method {
+        "B" | "b" => base64_decode(text)?,
+        "Q" | "q" => q_decode(text)?,
+        _ => return None,
+    };
+    Some((encoding, bytes, len))
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(text.len() / 4 * 3 + 2);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for byte in text.bytes() {
+        acc = (acc << 6) | BASE64.iter().position(|&c| c == byte)? as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    // a single character left over cannot make a byte.
+    if bits >= 6 {
+        return None;
+    }
+    Some(bytes)
+}
+
+fn q_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+    while let Some(byte) = input.next() {
+        bytes.push(match byte {
+            b'_' => b' ',
+            b'=' => {
+                let high = hex_value(input.next()?)?;
+                let low = hex_value(input.next()?)?;
+                high << 4 | low
+            }
+            byte => byte,
+        });
+    }
+    Some(bytes)
+}
+
+/// Decodes an RFC 2231 extended parameter value such as `utf-8'en'%E2%82%AC%20rates`,
+/// the value of a `name*=` parameter. The values of `name*0*=`, `name*1*=` and so on
+/// should be joined before decoding, as only the first one names the charset.
+/// Returns `None` if the value is malformed or in an unknown charset.
+pub fn decode_extended_value(input: &str) -> Option<String> {
+    let mut parts = input.splitn(3, '\'');
+    let charset = parts.next()?;
+    let _language = parts.next()?;
+    let value = parts.next()?.as_bytes();
+    let encoding = encoding_from_whatwg_label(charset)?;
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut i = 0;
+    while i < value.len() {
+        if value[i] == b'%' {
+            let high = hex_value(*value.get(i + 1)?)?;
+            let low = hex_value(*value.get(i + 2)?)?;
+            bytes.push(high << 4 | low);
+            i += 3;
+        } else {
+            bytes.push(value[i]);
+            i += 1;
+        }
+    }
+    encoding.decode(&bytes, DecoderTrap::Replace).ok()
+}
+
+/// Encodes `input` in `encoding` as encoded-words separated by spaces,
+/// where the header may be folded. Each encoded-word is at most 75 characters long
+/// and holds whole characters, starting and ending in the initial state of the encoding.
+/// The Q encoding is used when it is shorter, except for the encodings that are not
+/// ASCII-compatible, such as ISO-2022-JP, which always use the B encoding.
+/// Fails if `encoding` cannot represent `input`, or with `EncodingErrorKind::TooLong`
+/// if a character does not fit in an encoded-word by itself, e.g. for a long charset name.
+pub fn encode_header(input: &str, encoding: EncodingRef) -> Result<String, EncodingError> {
+    let charset = encoding.whatwg_name().unwrap_or(encoding.name());
+    let bytes = encoding.encode_checked(input, EncoderTrap::Strict)?;
+    let q = encoding.is_ascii_compatible() && q_len(&bytes) <= base64_len(&bytes);
+    let encoded_len = |bytes: &[u8]| if q { q_len(bytes) } else { base64_len(bytes) };
+    // "=?", "?Q?" and "?=" around the charset and the encoded text.
+    let max_len = MAX_ENCODED_WORD_LEN.checked_sub(charset.len() + 7).unwrap_or(0);
+
+    let mut output = String::new();
+    let mut start = 0;
+    let mut word = Vec::new();
+    for (i, c) in input.char_indices() {
+        let end = i + c.len_utf8();
+        let mut longer = encoding.encode_checked(&input[start..end], EncoderTrap::Strict)?;
+        if encoded_len(&longer) > max_len && start < i {
+            write_encoded_word(&word, charset, q, &mut output);
+            output.push(' ');
+            start = i;
+            longer = encoding.encode_checked(&input[i..end], EncoderTrap::Strict)?;
+        }
+        if encoded_len(&longer) > max_len {
+            return Err(EncodingError {
+                kind: EncodingErrorKind::TooLong,
+                offset: i,
+                sequence: input[i..end].as_bytes().to_vec(),
+                encoding: encoding.name(),
+            });
+        }
+        word = longer;
+    }
+    if !input.is_empty() {
+        write_encoded_word(&word, charset, q, &mut output);
+    }
+    Ok(output)
+}
+
+fn q_len(bytes: &[u8]) -> usize {
+    bytes.iter().map(|&byte| if is_q_literal(byte) || byte == b' ' { 1 } else { 3 }).sum()
+}
+
+fn base64_len(bytes: &[u8]) -> usize {
+    (bytes.len() + 2) / 3 * 4
+}
+
+fn write_encoded_word(bytes: &[u8], charset: &str, q: bool, output: &mut String) {
+    output.push_str("=?");
+    output.push_str(charset);
+    if q {
+        output.push_str("?Q?");
+        for &byte in bytes {
+            if byte == b' ' {
+                output.push('_');
+            } else if is_q_literal(byte) {
+                output.push(byte as char);
+            } else {
+                output.push_str(&format!("={:02X}", byte));
+            }
+        }
+    } else {
+        output.push_str("?B?");
+        for chunk in bytes.chunks(3) {
+            let acc = chunk.iter().fold(0u32, |acc, &byte| acc << 8 | byte as u32) <<
+                      (8 * (3 - chunk.len()));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    output.push(BASE64[(acc >> (18 - 6 * i)) as usize & 0x3f] as char);
+                } else {
+                    output.push('=');
+                }
+            }
+        }
+    }
+    output.push_str("?=");
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+    use all;
+    use testutils;
+    use types::{Encoding, RawDecoder, RawEncoder};
+
+    /// UTF-8 under a custom name.
+    struct Named(&'static str);
+
+    /// Returns UTF-8 named with `len` letters.
+    fn long_named(len: usize) -> EncodingRef {
+        let name = Box::leak("x".repeat(len).into_boxed_str());
+        Box::leak(Box::new(Named(name)))
+    }
+
+    impl Encoding for Named {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+        fn raw_encoder(&self) -> Box<RawEncoder> {
+            all::UTF_8.raw_encoder()
+        }
+        fn raw_decoder(&self) -> Box<RawDecoder> {
+            all::UTF_8.raw_decoder()
+        }
+    }
+
+    #[test]
+    fn test_decode_header() {
+        assert_eq!(decode_header("=?ISO-2022-JP?B?GyRCJDMkcyRLJEEkTxsoQg==?="),
+                   "\u{3053}\u{3093}\u{306b}\u{3061}\u{306f}");
+        assert_eq!(decode_header("Re: =?windows-1252?Q?caf=E9_cr=E8me?= recipe"),
+                   "Re: caf\u{e9} cr\u{e8}me recipe");
+        assert_eq!(decode_header("=?utf-8?b?4oKs?="), "\u{20ac}");
+        assert_eq!(decode_header("=?utf-8*en?q?=E2=82=AC?="), "\u{20ac}");
+        assert_eq!(decode_header("plain text"), "plain text");
+        assert_eq!(decode_header(""), "");
+        // the B encoding without padding.
+        assert_eq!(decode_header("=?utf-8?B?4oKsYQ?="), "\u{20ac}a");
+    }
+
+    #[test]
+    fn test_decode_header_adjacent_words() {
+        // whitespace between encoded-words goes, but not between them and text.
+        assert_eq!(decode_header("=?utf-8?Q?a?= \r\n =?utf-8?Q?b?= c =?utf-8?Q?d?="), "ab c d");
+        assert_eq!(decode_header("=?utf-8?Q?a?= =?iso-8859-1?Q?=E9?="), "a\u{e9}");
+        // a character split across encoded-words.
+        assert_eq!(decode_header("=?utf-8?Q?=E2=82?= =?UTF-8?Q?=AC?="), "\u{20ac}");
+        assert_eq!(decode_header("=?shift_jis?B?gg==?= =?sjis?B?oA==?="), "\u{3042}");
+        assert_eq!(decode_header("=?iso-2022-jp?B?GyRCJDMbKEI=?= =?iso-2022-jp?B?GyRCJHMbKEI=?="),
+                   "\u{3053}\u{3093}");
+        assert_eq!(decode_header("=?utf-8?Q?=E2=82?= =?iso-8859-1?Q?=AC?="),
+                   "\u{fffd}\u{ac}");
+    }
+
+    #[test]
+    fn test_decode_header_malformed() {
+        assert_eq!(decode_header("=?bogus?Q?a?="), "=?bogus?Q?a?=");
+        assert_eq!(decode_header("=?utf-8?X?a?="), "=?utf-8?X?a?=");
+        assert_eq!(decode_header("=?utf-8?Q?=E?="), "=?utf-8?Q?=E?=");
+        assert_eq!(decode_header("=?utf-8?B?4oKsY?="), "=?utf-8?B?4oKsY?=");
+        assert_eq!(decode_header("=?utf-8?B?4o!s?="), "=?utf-8?B?4o!s?=");
+        assert_eq!(decode_header("=?utf-8?Q?a b?="), "=?utf-8?Q?a b?=");
+        assert_eq!(decode_header("=?utf-8?Q?a"), "=?utf-8?Q?a");
+        assert_eq!(decode_header("=?=?utf-8?Q?a?="), "=?a");
+        assert_eq!(decode_header("=?utf-8?Q?=FF?="), "\u{fffd}");
+    }
+
+    #[test]
+    fn test_decode_extended_value() {
+        assert_eq!(decode_extended_value("utf-8'en'%E2%82%AC%20rates"),
+                   Some("\u{20ac} rates".to_string()));
+        assert_eq!(decode_extended_value("iso-8859-1''caf%e9.txt"),
+                   Some("caf\u{e9}.txt".to_string()));
+        assert_eq!(decode_extended_value("bogus''a"), None);
+        assert_eq!(decode_extended_value("utf-8'a"), None);
+        assert_eq!(decode_extended_value("utf-8''%E"), None);
+    }
+
+    #[test]
+    fn test_encode_header() {
+        assert_eq!(encode_header("caf\u{e9} cr\u{e8}me", all::WINDOWS_1252).unwrap(),
+                   "=?windows-1252?Q?caf=E9_cr=E8me?=");
+        assert_eq!(encode_header("\u{3053}\u{3093}\u{306b}\u{3061}\u{306f}", all::ISO_2022_JP)
+                       .unwrap(),
+                   "=?iso-2022-jp?B?GyRCJDMkcyRLJEEkTxsoQg==?=");
+        assert_eq!(encode_header("\u{20ac}\u{20ac}", all::UTF_8).unwrap(), "=?utf-8?B?4oKs4oKs?=");
+        assert_eq!(encode_header("a=b c", all::UTF_8).unwrap(), "=?utf-8?Q?a=3Db_c?=");
+        assert_eq!(encode_header("a=b_c?", all::UTF_8).unwrap(), "=?utf-8?B?YT1iX2M/?=");
+        assert_eq!(encode_header("", all::UTF_8).unwrap(), "");
+
+        let err = encode_header("a\u{3042}", all::WINDOWS_1252).unwrap_err();
+        assert_eq!(err.kind, EncodingErrorKind::Unmappable);
+        assert_eq!(err.offset, 1);
+    }
+
+    #[test]
+    fn test_encode_header_splits_words() {
+        let cases: &[(&str, EncodingRef)] = &[(testutils::JAPANESE_TEXT, all::ISO_2022_JP),
+                                              (testutils::JAPANESE_TEXT, all::WINDOWS_31J),
+                                              (testutils::JAPANESE_TEXT, all::UTF_8),
+                                              (testutils::KOREAN_TEXT, all::WINDOWS_949),
+                                              (testutils::SIMPLIFIED_CHINESE_TEXT, all::GB18030)];
+        for &(text, encoding) in cases {
+            let text: String = text.chars().filter(|&c| c != '\n').collect();
+            let header = encode_header(&text, encoding).unwrap();
+            assert!(header.split(' ').count() > 1);
+            for word in header.split(' ') {
+                assert!(word.len() <= MAX_ENCODED_WORD_LEN);
+                // every encoded-word decodes to whole characters by itself.
+                assert!(!decode_header(word).contains('\u{fffd}'), "{}", word);
+            }
+            assert_eq!(decode_header(&header), text);
+        }
+
+        // so is the Q encoding.
+        let text = format!("{}\u{e9}", "a".repeat(99));
+        let header = encode_header(&text, all::WINDOWS_1252).unwrap();
+        assert!(header.starts_with("=?windows-1252?Q?aaa"));
+        assert_eq!(header.split(' ').count(), 2);
+        assert!(header.split(' ').all(|word| word.len() <= MAX_ENCODED_WORD_LEN));
+        assert_eq!(decode_header(&header), text);
+    }
+
+    #[test]
+    fn test_encode_header_too_long() {
+        // leaves room for 6 characters of base64, which 4 bytes of UTF-8 do not fit in.
+        let encoding = long_named(62);
+        let header = encode_header("a\u{20ac}b", encoding).unwrap();
+        assert_eq!(header.split(' ').count(), 3);
+        assert!(header.split(' ').all(|word| word.len() <= MAX_ENCODED_WORD_LEN));
+        let err = encode_header("a\u{1f600}", encoding).unwrap_err();
+        assert_eq!((err.kind, err.offset), (EncodingErrorKind::TooLong, 1));
+        assert_eq!(err.unmappable_char(), Some('\u{1f600}'));
+
+        // no room at all.
+        let encoding = long_named(70);
+        let err = encode_header("a", encoding).unwrap_err();
+        assert_eq!((err.kind, err.offset), (EncodingErrorKind::TooLong, 0));
+        assert_eq!(encode_header("", encoding).unwrap(), "");
+    }
+
+    #[bench]
+    fn bench_encode_header(bencher: &mut test::Bencher) {
+        let s = testutils::JAPANESE_TEXT;
+        bencher.bytes = s.len() as u64;
+        bencher.iter(|| test::black_box(encode_header(s, all::ISO_2022_JP)))
+    }
+
+    #[bench]
+    fn bench_decode_header(bencher: &mut test::Bencher) {
+        let s = encode_header(testutils::JAPANESE_TEXT, all::ISO_2022_JP).unwrap();
+        bencher.bytes = s.len() as u64;
+        bencher.iter(|| test::black_box(decode_header(&s)))
+    }
+}
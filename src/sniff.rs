@@ -0,0 +1,456 @@
+// Copyright 2016 Mozilla Foundation. See the COPYRIGHT
+// file at the top-level directory of this distribution.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE.txt or https://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Sniffing the encoding a document declares for itself in its first bytes.
+
+use std::{cmp, str};
+use all;
+use label::encoding_from_whatwg_label;
+use types::EncodingRef;
+
+/// The number of bytes the HTML prescan looks at.
+const HTML_PRESCAN_LIMIT: usize = 1024;
+
+/// The number of bytes a CSS `@charset` rule has to fit in.
+const CSS_CHARSET_LIMIT: usize = 1024;
+
+fn is_space(byte: u8) -> bool {
+    match byte {
+        b'\t' | b'\n' | b'\x0c' | b'\r' | b' ' => true,
+        _ => false,
+    }
+}
+
+fn starts_with_ignore_case(input: &[u8], prefix: &[u8]) -> bool {
+    input.len() >= prefix.len() && input[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn find(input: &[u8], needle: &[u8]) -> Option<usize> {
+    input.windows(needle.len()).position(|window| window == needle)
+}
+
+fn label(label: &[u8]) -> Option<EncodingRef> {
+    str::from_utf8(label).ok().and_then(encoding_from_whatwg_label)
+}
+
+/// Returns the encoding declared by a `<meta>` element within the first 1024 bytes
+/// of an HTML document, if any. UTF-16 is read as UTF-8 and x-user-defined as windows-1252,
+/// as the document could not have been parsed this far otherwise.
+/// Corresponds to WHATWG "prescan a byte stream to determine its encoding".
+pub fn html_meta(input: &[u8]) -> Option<EncodingRef> {
+    let input = &input[..cmp::min(input.len(), HTML_PRESCAN_LIMIT)];
+    let mut prescan = Prescan { input: input, pos: 0 };
+    let encoding = prescan.run()?;
+    match encoding.whatwg_name() {
+        Some("utf-16be") | Some("utf-16le") => Some(all::UTF_8),
+        Some("x-user-defined") => Some(all::WINDOWS_1252),
+        _ => Some(encoding),
+    }
+}
+
+/// The state of the HTML prescan. Running out of input aborts the prescan,
+/// which the methods signal by returning `None`.
+struct Prescan<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Prescan<'a> {
+    fn byte(&self) -> Option<u8> {
+        self.input.get(self.pos).cloned()
+    }
+
+    fn run(&mut self) -> Option<EncodingRef> {
+        while self.pos < self.input.len() {
+            let rest = &self.input[self.pos..];
+            if rest.starts_with(b"<!--") {
+                // the dashes of `<!--` also end the comment, as in `<!-->`.
+                self.pos += 2 + find(&rest[2..], b"-->")? + 2;
+            } else if starts_with_ignore_case(rest, b"<meta") &&
+                      rest.len() > 5 && (is_space(rest[5]) || rest[5] == b'/') {
+                self.pos += 5;
+                if let Some(encoding) = self.meta()? {
+                    return Some(encoding);
+                }
+            } else if rest.len() > 2 && rest[0] == b'<' &&
+                      (rest[1].is_ascii_alphabetic() ||
+                       rest[1] == b'/' && rest[2].is_ascii_alphabetic()) {
+                while !is_space(self.byte()?) && self.byte()? != b'>' {
+                    self.pos += 1;
+                }
+                while self.attribute()?.is_some() {}
+            } else if rest.starts_with(b"<!") || rest.starts_with(b"</") ||
+                      rest.starts_with(b"<?") {
+                self.pos += find(rest, b">")?;
+            }
+            self.pos += 1;
+        }
+        None
+    }
+
+    /// Processes the attributes of a `<meta>` element and returns the encoding it declares.
+    fn meta(&mut self) -> Option<Option<EncodingRef>> {
+        let mut names = Vec::new();
+        let mut got_pragma = false;
+        let mut need_pragma = None;
+        // `Some(None)` once an unknown label has been declared.
+        let mut charset = None;
+        while let Some((name, value)) = self.attribute()? {
+            if names.contains(&name) {
+                continue;
+            }
+            match &name[..] {
+                b"http-equiv" => got_pragma |= value == b"content-type",
+                b"content" if charset.is_none() => {
+                    if let Some(encoding) = charset_from_content(&value).and_then(label) {
+                        charset = Some(Some(encoding));
+                        need_pragma = Some(true);
+                    }
+                }
+                b"charset" if charset.is_none() => {
+                    charset = Some(label(&value));
+                    need_pragma = Some(false);
+                }
+                _ => {}
+            }
+            names.push(name);
+        }
+        Some(match need_pragma {
+            Some(true) if !got_pragma => None,
+            _ => charset.and_then(|charset| charset),
+        })
+    }
+
+    /// Returns the next attribute with its name and unquoted value in lowercase,
+    /// or `Some(None)` at the end of the tag.
+    /// Corresponds to WHATWG "get an attribute".
+    fn attribute(&mut self) -> Option<Option<(Vec<u8>, Vec<u8>)>> {
+        while is_space(self.byte()?) || self.byte()? == b'/' {
+            self.pos += 1;
+        }
+        if self.byte()? == b'>' {
+            return Some(None);
+        }
+        let mut name = Vec::new();
+        let mut value = Vec::new();
+        loop {
+            match self.byte()? {
+                b'=' if !name.is_empty() => break,
+                b'/' | b'>' => return Some(Some((name, value))),
+                byte if is_space(byte) => {
+                    while is_space(self.byte()?) {
+                        self.pos += 1;
+                    }
+                    if self.byte()? != b'=' {
+                        return Some(Some((name, value)));
+                    }
+                    break;
+                }
+                byte => name.push(byte.to_ascii_lowercase()),
+            }
+            self.pos += 1;
+        }
+        // skips `=`.
+        self.pos += 1;
+        while is_space(self.byte()?) {
+            self.pos += 1;
+        }
+        match self.byte()? {
+            quote @ b'"' | quote @ b'\'' => {
+                loop {
+                    self.pos += 1;
+                    match self.byte()? {
+                        byte if byte == quote => break,
+                        byte => value.push(byte.to_ascii_lowercase()),
+                    }
+                }
+                self.pos += 1;
+                return Some(Some((name, value)));
+            }
+            b'>' => return Some(Some((name, value))),
+            _ => {}
+        }
+        loop {
+            match self.byte()? {
+                byte if is_space(byte) || byte == b'>' => return Some(Some((name, value))),
+                byte => value.push(byte.to_ascii_lowercase()),
+            }
+            self.pos += 1;
+        }
+    }
+}
+
+/// Returns the encoding label in the lowercased `content` attribute of `<meta>`, if any.
+/// Corresponds to WHATWG "extract a character encoding from a meta element".
+fn charset_from_content(content: &[u8]) -> Option<&[u8]> {
+    let mut pos = 0;
+    loop {
+        pos += find(&content[pos..], b"charset")? + 7;
+        while content.get(pos).map_or(false, |&byte| is_space(byte)) {
+            pos += 1;
+        }
+        if content.get(pos) == Some(&b'=') {
+            pos += 1;
+            break;
+        }
+    }
+    while content.get(pos).map_or(false, |&byte| is_space(byte)) {
+        pos += 1;
+    }
+    let rest = &content[pos..];
+    match rest.first() {
+        Some(&quote) if quote == b'"' || quote == b'\'' => {
+            let end = rest[1..].iter().position(|&byte| byte == quote)?;
+            Some(&rest[1..end + 1])
+        }
+        Some(_) => {
+            let end = rest.iter().position(|&byte| is_space(byte) || byte == b';');
+            Some(&rest[..end.unwrap_or(rest.len())])
+        }
+        None => None,
+    }
+}
+
+fn is_xml_space(byte: u8) -> bool {
+    match byte {
+        b'\t' | b'\n' | b'\r' | b' ' => true,
+        _ => false,
+    }
+}
+
+/// Returns the encoding of an XML document from its Byte Order Mark,
+/// the layout of `<?xml` in its first bytes or its `encoding` declaration, if any.
+/// Documents without any of them are in UTF-8 unless an external source says otherwise.
+/// The UCS-4 and EBCDIC families are recognized but return `None`,
+/// as there are no such encodings to read them with.
+/// Follows Appendix F.1 of the XML 1.0 Recommendation.
+pub fn xml_declaration(input: &[u8]) -> Option<EncodingRef> {
+    match input {
+        [0x00, 0x00, 0xfe, 0xff, ..] | [0xff, 0xfe, 0x00, 0x00, ..] |
+        [0x00, 0x00, 0xff, 0xfe, ..] | [0xfe, 0xff, 0x00, 0x00, ..] => None,
+        [0xfe, 0xff, ..] => Some(all::UTF_16BE),
+        [0xff, 0xfe, ..] => Some(all::UTF_16LE),
+        [0xef, 0xbb, 0xbf, ..] => Some(all::UTF_8),
+        [0x00, 0x00, 0x00, 0x3c, ..] | [0x3c, 0x00, 0x00, 0x00, ..] |
+        [0x00, 0x00, 0x3c, 0x00, ..] | [0x00, 0x3c, 0x00, 0x00, ..] => None,
+        // the declaration could only name UTF-16 itself.
+        [0x00, 0x3c, 0x00, 0x3f, ..] => Some(all::UTF_16BE),
+        [0x3c, 0x00, 0x3f, 0x00, ..] => Some(all::UTF_16LE),
+        // `<?xm` in EBCDIC.
+        [0x4c, 0x6f, 0xa7, 0x94, ..] => None,
+        _ => {
+            let encoding = xml_encoding_declaration(input)?;
+            match encoding.whatwg_name() {
+                // the declaration was readable as ASCII after all.
+                Some("utf-16be") | Some("utf-16le") => Some(all::UTF_8),
+                _ => Some(encoding),
+            }
+        }
+    }
+}
+
+/// Returns the encoding named by the `encoding` pseudo-attribute of `<?xml ... ?>`.
+fn xml_encoding_declaration(input: &[u8]) -> Option<EncodingRef> {
+    if !input.starts_with(b"<?xml") || !input.get(5).map_or(false, |&byte| is_xml_space(byte)) {
+        return None;
+    }
+    let mut pos = 5;
+    loop {
+        while input.get(pos).map_or(false, |&byte| is_xml_space(byte)) {
+            pos += 1;
+        }
+        let name_len = input[pos..].iter().take_while(|byte| byte.is_ascii_lowercase()).count();
+        if name_len == 0 {
+            // `?>` or a malformed declaration.
+            return None;
+        }
+        let name = &input[pos..pos + name_len];
+        pos += name_len;
+        while input.get(pos).map_or(false, |&byte| is_xml_space(byte)) {
+            pos += 1;
+        }
+        if input.get(pos) != Some(&b'=') {
+            return None;
+        }
+        pos += 1;
+        while input.get(pos).map_or(false, |&byte| is_xml_space(byte)) {
+            pos += 1;
+        }
+        let quote = match input.get(pos) {
+            Some(&quote) if quote == b'"' || quote == b'\'' => quote,
+            _ => return None,
+        };
+        let value_len = input[pos + 1..].iter().position(|&byte| byte == quote)?;
+        let value = &input[pos + 1..pos + 1 + value_len];
+        if name == b"encoding" {
+            return label(value);
+        }
+        pos += value_len + 2;
+    }
+}
+
+/// Returns the encoding named by a `@charset "...";` rule at the very start of a stylesheet,
+/// if any. UTF-16 is read as UTF-8, as the rule could not have been read otherwise.
+/// A Byte Order Mark takes precedence and should be checked first.
+/// Corresponds to the `@charset` step of CSS Syntax "determine the fallback encoding".
+pub fn css_charset(input: &[u8]) -> Option<EncodingRef> {
+    let input = &input[..cmp::min(input.len(), CSS_CHARSET_LIMIT)];
+    if !input.starts_with(b"@charset \"") {
+        return None;
+    }
+    let rest = &input[10..];
+    let end = rest.iter().position(|&byte| byte == b'"')?;
+    if rest.get(end + 1) != Some(&b';') {
+        return None;
+    }
+    let encoding = label(&rest[..end])?;
+    match encoding.whatwg_name() {
+        Some("utf-16be") | Some("utf-16le") => Some(all::UTF_8),
+        _ => Some(encoding),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+    use super::*;
+
+    fn name(encoding: Option<EncodingRef>) -> Option<&'static str> {
+        encoding.and_then(|encoding| encoding.whatwg_name())
+    }
+
+    #[test]
+    fn test_html_meta() {
+        let treaty = include_bytes!("examples/outer-space-treaty.html");
+        assert_eq!(name(html_meta(treaty)), Some("utf-8"));
+
+        assert_eq!(name(html_meta(b"<meta charset=Shift_JIS>")), Some("shift_jis"));
+        assert_eq!(name(html_meta(b"<META CHARSET = 'euc-kr' >")), Some("euc-kr"));
+        assert_eq!(name(html_meta(b"<meta/charset=\"koi8-r\"/>")), Some("koi8-r"));
+        assert_eq!(name(html_meta(b"<meta http-equiv=Content-Type \
+                                   content='text/html; charset=\"iso-8859-2\"'>")),
+                   Some("iso-8859-2"));
+        assert_eq!(name(html_meta(b"<meta content=\"text/html;charset=gbk\" \
+                                   http-equiv=\"content-type\">")),
+                   Some("gbk"));
+        // `content` needs `http-equiv`, `charset` does not.
+        assert_eq!(name(html_meta(b"<meta content='text/html; charset=gbk'>")), None);
+        assert_eq!(name(html_meta(b"<meta content='charset=gbk' charset=big5>")), None);
+        assert_eq!(name(html_meta(b"<meta charset=big5 content='charset=gbk'>")), Some("big5"));
+        // only the first of duplicate attributes counts.
+        assert_eq!(name(html_meta(b"<meta charset=big5 charset=gbk>")), Some("big5"));
+        assert_eq!(name(html_meta(b"<meta charset=bogus charset=gbk>")), None);
+        // an unknown label lets the prescan go on.
+        assert_eq!(name(html_meta(b"<meta charset=bogus><meta charset=euc-jp>")),
+                   Some("euc-jp"));
+        assert_eq!(name(html_meta(b"<meta http-equiv=content-type content='charset=\"gbk'>\
+                                   <meta charset=big5>")),
+                   Some("big5"));
+    }
+
+    #[test]
+    fn test_html_meta_skips_markup() {
+        assert_eq!(name(html_meta(b"<!-- <meta charset=gbk> --><meta charset=big5>")),
+                   Some("big5"));
+        assert_eq!(name(html_meta(b"<!--><meta charset=big5>")), Some("big5"));
+        assert_eq!(name(html_meta(b"<!DOCTYPE html><?xml charset=gbk?><meta charset=big5>")),
+                   Some("big5"));
+        assert_eq!(name(html_meta(b"<title x='<meta charset=gbk>'><meta charset=big5>")),
+                   Some("big5"));
+        assert_eq!(name(html_meta(b"</p charset=gbk><meta charset=big5>")), Some("big5"));
+        assert_eq!(name(html_meta(b"<metal charset=gbk><meta charset=big5>")), Some("big5"));
+        // not a tag, so the `<meta>` inside is seen.
+        assert_eq!(name(html_meta(b"< p x='<meta charset=big5>'>")), Some("big5"));
+        assert_eq!(name(html_meta(b"")), None);
+        assert_eq!(name(html_meta(b"<!-- <meta charset=gbk>")), None);
+        assert_eq!(name(html_meta(b"<meta charset=gbk")), None);
+    }
+
+    #[test]
+    fn test_html_meta_overrides() {
+        assert_eq!(name(html_meta(b"<meta charset=utf-16>")), Some("utf-8"));
+        assert_eq!(name(html_meta(b"<meta charset=utf-16be>")), Some("utf-8"));
+        assert_eq!(name(html_meta(b"<meta charset=x-user-defined>")), Some("windows-1252"));
+    }
+
+    #[test]
+    fn test_html_meta_limit() {
+        let mut input = vec![b' '; HTML_PRESCAN_LIMIT - 24];
+        input.extend_from_slice(b"<meta charset=windows-1251>");
+        assert_eq!(name(html_meta(&input)), None);
+        input.drain(..3);
+        assert_eq!(name(html_meta(&input)), Some("windows-1251"));
+    }
+
+    #[test]
+    fn test_xml_declaration() {
+        assert_eq!(name(xml_declaration(b"<?xml version=\"1.0\" encoding=\"EUC-JP\"?><a/>")),
+                   Some("euc-jp"));
+        assert_eq!(name(xml_declaration(b"<?xml version='1.0' encoding = 'koi8-r' \
+                                          standalone='yes'?>")),
+                   Some("koi8-r"));
+        assert_eq!(name(xml_declaration(b"<?xml\r\n\tencoding=\"windows-1251\"?>")),
+                   Some("windows-1251"));
+        assert_eq!(name(xml_declaration(b"<?xml version=\"1.0\" encoding=\"UTF-16\"?>")),
+                   Some("utf-8"));
+        assert_eq!(name(xml_declaration(b"<?xml version=\"1.0\"?><a/>")), None);
+        assert_eq!(name(xml_declaration(b"<?xml version=\"1.0\" encoding=\"bogus\"?>")), None);
+        assert_eq!(name(xml_declaration(b"<?xml encoding=\"gbk?>")), None);
+        assert_eq!(name(xml_declaration(b"<?xml-stylesheet encoding=\"gbk\"?>")), None);
+        assert_eq!(name(xml_declaration(b" <?xml encoding=\"gbk\"?>")), None);
+        assert_eq!(name(xml_declaration(b"<a/>")), None);
+        assert_eq!(name(xml_declaration(b"")), None);
+    }
+
+    #[test]
+    fn test_xml_declaration_families() {
+        // a Byte Order Mark overrides the declaration.
+        assert_eq!(name(xml_declaration(b"\xef\xbb\xbf<?xml encoding=\"gbk\"?>")),
+                   Some("utf-8"));
+        assert_eq!(name(xml_declaration(b"\xfe\xff\x00<\x00?")), Some("utf-16be"));
+        assert_eq!(name(xml_declaration(b"\xff\xfe<\x00?\x00")), Some("utf-16le"));
+        assert_eq!(name(xml_declaration(b"\x00<\x00?\x00x\x00m\x00l")), Some("utf-16be"));
+        assert_eq!(name(xml_declaration(b"<\x00?\x00x\x00m\x00l\x00")), Some("utf-16le"));
+        // UCS-4 and EBCDIC.
+        assert_eq!(name(xml_declaration(b"\x00\x00\xfe\xff\x00\x00\x00<")), None);
+        assert_eq!(name(xml_declaration(b"\xff\xfe\x00\x00<\x00\x00\x00")), None);
+        assert_eq!(name(xml_declaration(b"\x00\x00\x00<\x00\x00\x00?")), None);
+        assert_eq!(name(xml_declaration(b"\x4c\x6f\xa7\x94\x93\x40")), None);
+    }
+
+    #[test]
+    fn test_css_charset() {
+        assert_eq!(name(css_charset(b"@charset \"ISO-8859-5\";\nbody {}")), Some("iso-8859-5"));
+        assert_eq!(name(css_charset(b"@charset \"utf-16le\";")), Some("utf-8"));
+        assert_eq!(name(css_charset(b"@charset \"bogus\";")), None);
+        // the rule has to be written exactly so.
+        assert_eq!(name(css_charset(b"@charset 'gbk';")), None);
+        assert_eq!(name(css_charset(b"@charset  \"gbk\";")), None);
+        assert_eq!(name(css_charset(b"@CHARSET \"gbk\";")), None);
+        assert_eq!(name(css_charset(b"@charset \"gbk\" ;")), None);
+        assert_eq!(name(css_charset(b" @charset \"gbk\";")), None);
+        assert_eq!(name(css_charset(b"@charset \"gbk")), None);
+
+        let mut input = b"@charset \"".to_vec();
+        input.extend_from_slice(&[b' '; CSS_CHARSET_LIMIT - 14]);
+        input.extend_from_slice(b"gbk\";");
+        assert_eq!(name(css_charset(&input)), None);
+        input.remove(10);
+        assert_eq!(name(css_charset(&input)), Some("gbk"));
+    }
+
+    #[bench]
+    fn bench_html_meta(bencher: &mut test::Bencher) {
+        let mut input = b"<!DOCTYPE html><html lang=ja><head><title>x</title>".to_vec();
+        input.extend_from_slice(b"<meta http-equiv=Content-Type content='text/html; \
+                                  charset=Shift_JIS'>");
+        bencher.bytes = input.len() as u64;
+        bencher.iter(|| test::black_box(html_meta(&input)))
+    }
+}
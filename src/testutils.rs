@@ -193,36 +193,36 @@ macro_rules! assert_expected {
 
 macro_rules! assert_feed_ok {
     ($this:expr, $processed:expr, $unprocessed:expr, $output:expr) => (
-//        assert_expected!($this.process_feed_ok(&$processed, &$unprocessed, &$output),
-//                         "raw_feed", |r| r)
+        assert_expected!($this.process_feed_ok(&$processed, &$unprocessed, &$output),
+                         "raw_feed", |r| r)
     );
 }
 
 macro_rules! assert_feed_err {
     ($this:expr, $backup:expr, $processed:expr, $problem:expr, $remaining:expr, $output:expr) => (
-//        assert_expected!($this.process_feed_err($backup, &$processed, &$problem, &$remaining,
-//                                                &$output),
-//                         "raw_feed", |r| r)
+        assert_expected!($this.process_feed_err($backup, &$processed, &$problem, &$remaining,
+                                                &$output),
+                         "raw_feed", |r| r)
     );
     ($this:expr, $processed:expr, $problem:expr, $remaining:expr, $output:expr) => (
-//        assert_feed_err!($this, 0, $processed, $problem, $remaining, $output)
+        assert_feed_err!($this, 0, $processed, $problem, $remaining, $output)
     );
 }
 
 macro_rules! assert_finish_ok {
     ($this:expr, $output:expr) => (
-//        assert_expected!($this.process_finish_ok(&$output),
-//                         "raw_finish", |r: (usize, Option<isize>)| r.0)
+        assert_expected!($this.process_finish_ok(&$output),
+                         "raw_finish", |r: (usize, Option<isize>)| r.1)
     );
 }
 
 macro_rules! assert_finish_err {
     ($this:expr, $backup:expr, $output:expr) => (
-//        assert_expected!($this.process_finish_err($backup, &$output),
-//                         "raw_finish", |r: (usize, Option<isize>)| r.0)
+        assert_expected!($this.process_finish_err($backup, &$output),
+                         "raw_finish", |r: (usize, Option<isize>)| r.1)
     );
     ($this:expr, $output:expr) => (
-//        assert_finish_err!($this, 0, $output)
+        assert_finish_err!($this, 0, $output)
     );
 }
 
@@ -51,6 +51,10 @@
 //! It still has to feed the input bytes starting at the second offset again.
 //!
 use std::borrow::Cow;
+use std::error;
+use std::fmt;
+use std::io;
+extern crate encoding_rs;
 
 /// Error information from either encoder or decoder.
 pub struct CodecError {
@@ -86,6 +90,23 @@ pub trait ByteWriter {
     fn as_mut_vec(&mut self) -> Option<&mut Vec<u8>> {
         None
     }
+
+    /// Returns a slice of at least `min_len` bytes that the encoder can write
+    /// into directly, without copying through an intermediate buffer.
+    /// The written bytes become a part of the output when `commit_slice` is called,
+    /// so the two methods must be implemented together.
+    /// By default this method returns `None`, and the encoder writes with `write_bytes`.
+    fn writable_slice(&mut self, _min_len: usize) -> Option<&mut [u8]> {
+        None
+    }
+
+    /// Appends the first `len` bytes of the slice last returned by `writable_slice`
+    /// to the output.
+    /// By default this method panics, as it is only called after `writable_slice`
+    /// has returned a slice and the written bytes would be lost otherwise.
+    fn commit_slice(&mut self, _len: usize) {
+        panic!("ByteWriter implements writable_slice but not commit_slice");
+    }
 }
 
 impl ByteWriter for Vec<u8> {
@@ -127,6 +148,26 @@ pub trait StringWriter {
     fn as_mut_string(&mut self) -> Option<&mut String> {
         None
     }
+
+    /// Returns a slice of at least `min_len` bytes that the decoder can write
+    /// UTF-8 into directly, without copying through an intermediate buffer.
+    /// The written bytes become a part of the output when `commit_slice` is called,
+    /// so the two methods must be implemented together.
+    /// By default this method returns `None`, and the decoder writes with `write_str`.
+    fn writable_slice(&mut self, _min_len: usize) -> Option<&mut [u8]> {
+        None
+    }
+
+    /// Appends the first `len` bytes of the slice last returned by `writable_slice`
+    /// to the output.
+    /// By default this method panics, as it is only called after `writable_slice`
+    /// has returned a slice and the written bytes would be lost otherwise.
+    ///
+    /// This method is unsafe because the caller must ensure that
+    /// these bytes are valid UTF-8.
+    unsafe fn commit_slice(&mut self, _len: usize) {
+        panic!("StringWriter implements writable_slice but not commit_slice");
+    }
 }
 
 impl StringWriter for String {
@@ -198,6 +239,38 @@ pub trait RawDecoder: 'static {
     fn raw_finish(&mut self, output: &mut StringWriter) -> Option<CodecError>;
 }
 
+/// The encoding of encoding_rs that corresponds to an encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncodingRsMapping {
+    /// The encoding_rs encoding encodes and decodes the same as the encoding.
+    Exact(&'static encoding_rs::Encoding),
+    /// The encoding_rs encoding encodes and decodes everything that the encoding does
+    /// the same way, but also accepts some input that the encoding reports as an error.
+    Superset(&'static encoding_rs::Encoding),
+    /// The encoding_rs encoding decodes the same as the encoding, but encodes into
+    /// its `output_encoding()` instead, e.g. UTF-16LE, UTF-16BE and replacement into UTF-8.
+    DecodeOnly(&'static encoding_rs::Encoding),
+}
+
+impl EncodingRsMapping {
+    /// Returns the encoding_rs encoding.
+    pub fn encoding(&self) -> &'static encoding_rs::Encoding {
+        match *self {
+            EncodingRsMapping::Exact(encoding) |
+            EncodingRsMapping::Superset(encoding) |
+            EncodingRsMapping::DecodeOnly(encoding) => encoding,
+        }
+    }
+
+    /// Returns true if the encoding_rs encoding is the same as the encoding.
+    pub fn is_exact(&self) -> bool {
+        match *self {
+            EncodingRsMapping::Exact(_) => true,
+            EncodingRsMapping::Superset(_) | EncodingRsMapping::DecodeOnly(_) => false,
+        }
+    }
+}
+
 /// A trait object using dynamic dispatch which is a sendable reference to the encoding,
 /// for code where the encoding is not known at compile-time.
 pub type EncodingRef = &'static (Encoding + Send + Sync);
@@ -215,12 +288,25 @@ pub trait Encoding {
         None
     }
 
+    /// Returns the corresponding encoding of encoding_rs, if any.
+    /// Encodings implemented outside this crate can declare their counterpart here
+    /// to be usable with `to_encoding_rs`.
+    fn encoding_rs_mapping(&self) -> Option<EncodingRsMapping> {
+        None
+    }
+
     /// Creates a new encoder.
     fn raw_encoder(&self) -> Box<RawEncoder>;
 
     /// Creates a new decoder.
     fn raw_decoder(&self) -> Box<RawDecoder>;
 
+    /// Returns true if this encoding maps ASCII to itself, like `RawEncoder::is_ascii_compatible`.
+    /// The default creates an encoder to ask; built-in encodings answer without allocating.
+    fn is_ascii_compatible(&self) -> bool {
+        self.raw_encoder().is_ascii_compatible()
+    }
+
     /// An easy-to-use interface to `RawEncoder`.
     /// On the encoder error `trap` is called,
     /// which may return a replacement sequence to continue processing,
@@ -236,38 +322,95 @@ pub trait Encoding {
                  trap: EncoderTrap,
                  ret: &mut ByteWriter)
                  -> Result<(), Cow<'static, str>> {
-        // we don't need to keep `unprocessed` here;
-        // `raw_feed` should process as much input as possible.
-        let mut encoder = self.raw_encoder();
-        let mut remaining = 0;
-
-        loop {
-            let (offset, err) = encoder.raw_feed(&input[remaining..], ret);
-            let unprocessed = remaining + offset;
-            match err {
-                Some(err) => {
-                    remaining = (remaining as isize + err.upto) as usize;
-                    if !trap.trap(&mut *encoder, &input[unprocessed..remaining], ret) {
-                        return Err(err.cause);
-                    }
-                }
-                None => {
-                    remaining = input.len();
-                    match encoder.raw_finish(ret) {
-                        Some(err) => {
-                            remaining = (remaining as isize + err.upto) as usize;
-                            if !trap.trap(&mut *encoder, &input[unprocessed..remaining], ret) {
-                                return Err(err.cause);
-                            }
-                        }
-                        None => {}
-                    }
-                    if remaining >= input.len() {
-                        return Ok(());
-                    }
-                }
-            }
-        }
+        with_encoder_trap(trap, |trap| self.encode_to_with(input, trap, ret))
+    }
+
+    /// Same as `encode` but calls the closure `trap` on the encoder error,
+    /// which may keep a state unlike `EncoderTrap::Call`.
+    /// The closure is given the current encoder, input and output writer,
+    /// and should return true only when it is fine to keep going.
+    fn encode_with(&self,
+                   input: &str,
+                   trap: &mut FnMut(&mut RawEncoder, &str, &mut ByteWriter) -> bool)
+                   -> Result<Vec<u8>, Cow<'static, str>> {
+        let mut ret = Vec::new();
+        self.encode_to_with(input, trap, &mut ret).map(|_| ret)
+    }
+
+    /// Same as `encode_to` but calls the closure `trap` on the encoder error.
+    /// See `encode_with`.
+    fn encode_to_with(&self,
+                      input: &str,
+                      trap: &mut FnMut(&mut RawEncoder, &str, &mut ByteWriter) -> bool,
+                      ret: &mut ByteWriter)
+                      -> Result<(), Cow<'static, str>> {
+        encode_raw(&mut *self.raw_encoder(),
+                   input,
+                   &mut |encoder, _, input, output| trap(encoder, input, output),
+                   ret)
+    }
+
+    /// Same as `encode` but replaces every unrepresentable character with `replacement`
+    /// in this encoding (e.g. `"\u{1a}"` or `"\u{3013}"`), like `EncoderTrap::Replace` does
+    /// with `?`. Fails when `replacement` cannot be represented in this encoding either.
+    fn encode_replacing(&self,
+                        input: &str,
+                        replacement: &str)
+                        -> Result<Vec<u8>, Cow<'static, str>> {
+        let mut ret = Vec::new();
+        self.encode_to_replacing(input, replacement, &mut ret).map(|_| ret)
+    }
+
+    /// Same as `encode_to` but replaces every unrepresentable character with `replacement`.
+    /// See `encode_replacing`.
+    fn encode_to_replacing(&self,
+                           input: &str,
+                           replacement: &str,
+                           ret: &mut ByteWriter)
+                           -> Result<(), Cow<'static, str>> {
+        with_fallible_trap(|encoder, _, output| reencode(encoder, replacement, output),
+                           |trap| self.encode_to_with(input, trap, ret))
+    }
+
+    /// Same as `encode` but returns an `EncodingError` with the position
+    /// of the unrepresentable character on the failure.
+    fn encode_checked(&self, input: &str, trap: EncoderTrap) -> Result<Vec<u8>, EncodingError> {
+        let mut ret = Vec::new();
+        let mut error = None;
+        let result = encode_raw(&mut *self.raw_encoder(),
+                                input,
+                                &mut |encoder, offset, input, output| {
+                                    let kind = match trap.try_trap(encoder, input, output) {
+                                        Ok(true) => return true,
+                                        Ok(false) => EncodingErrorKind::Unmappable,
+                                        Err(_) => EncodingErrorKind::UnmappableReplacement,
+                                    };
+                                    error = Some(EncodingError {
+                                        kind: kind,
+                                        offset: offset,
+                                        sequence: input.as_bytes().to_vec(),
+                                        encoding: self.name(),
+                                    });
+                                    false
+                                },
+                                &mut ret);
+        // only a failed trap stops the encoder.
+        result.map(|_| ret).map_err(|_| error.unwrap())
+    }
+
+    /// Returns the byte offset in `input` and the character of
+    /// the first character not representable in this encoding, if any.
+    /// An error at the end of `input`, which some encoders report on finishing,
+    /// has no such character and is not returned.
+    fn first_unmappable(&self, input: &str) -> Option<(usize, char)> {
+        self.encode_checked(input, EncoderTrap::Strict).err().and_then(|err| {
+            input[err.offset..].chars().next().map(|ch| (err.offset, ch))
+        })
+    }
+
+    /// Returns true if `input` can be encoded in this encoding without any error.
+    fn can_encode(&self, input: &str) -> bool {
+        self.encode_checked(input, EncoderTrap::Strict).is_ok()
     }
 
     /// An easy-to-use interface to `RawDecoder`.
@@ -279,6 +422,71 @@ pub trait Encoding {
         self.decode_to(input, trap, &mut ret).map(|_| ret)
     }
 
+    /// Same as `encode` but may borrow from `input` instead of allocating,
+    /// e.g. when `input` is pure ASCII and the encoding is ASCII-compatible.
+    fn encode_cow<'a>(&self,
+                      input: &'a str,
+                      trap: EncoderTrap)
+                      -> Result<Cow<'a, [u8]>, Cow<'static, str>> {
+        if input.is_ascii() && self.is_ascii_compatible() {
+            return Ok(Cow::Borrowed(input.as_bytes()));
+        }
+        self.encode(input, trap).map(Cow::Owned)
+    }
+
+    /// Same as `decode` but may borrow from `input` instead of allocating,
+    /// e.g. when `input` is pure ASCII and the encoding is ASCII-compatible.
+    fn decode_cow<'a>(&self,
+                      input: &'a [u8],
+                      trap: DecoderTrap)
+                      -> Result<Cow<'a, str>, Cow<'static, str>> {
+        if input.is_ascii() && self.is_ascii_compatible() {
+            return Ok(Cow::Borrowed(unsafe { ::std::str::from_utf8_unchecked(input) }));
+        }
+        self.decode(input, trap).map(Cow::Owned)
+    }
+
+    /// Decodes `input` replacing every malformed sequence with U+FFFD like `DecoderTrap::Replace`,
+    /// and returns the result along with the byte offset and the bytes of every malformed sequence.
+    fn decode_with_errors<'a>(&self, input: &'a [u8]) -> (String, Vec<(usize, &'a [u8])>) {
+        let mut ret = String::new();
+        let mut errors = Vec::new();
+        {
+            let mut trap = |_: &mut RawDecoder, offset, problem: &[u8], output: &mut StringWriter| {
+                errors.push((offset, &input[offset..offset + problem.len()]));
+                output.write_char('\u{fffd}');
+                true
+            };
+            // the trap never fails.
+            let _ = decode_raw(&mut *self.raw_decoder(), input, &mut trap, &mut ret);
+        }
+        (ret, errors)
+    }
+
+    /// Same as `decode` but returns an `EncodingError` with the position
+    /// of the malformed sequence on the failure.
+    fn decode_checked(&self, input: &[u8], trap: DecoderTrap) -> Result<String, EncodingError> {
+        let mut ret = String::new();
+        let mut error = None;
+        let result = decode_raw(&mut *self.raw_decoder(),
+                                input,
+                                &mut |decoder, offset, input, output| {
+                                    if trap.trap(decoder, input, output) {
+                                        return true;
+                                    }
+                                    error = Some(EncodingError {
+                                        kind: EncodingErrorKind::Malformed,
+                                        offset: offset,
+                                        sequence: input.to_vec(),
+                                        encoding: self.name(),
+                                    });
+                                    false
+                                },
+                                &mut ret);
+        // only a failed trap stops the decoder.
+        result.map(|_| ret).map_err(|_| error.unwrap())
+    }
+
     /// Decode into a `StringWriter`.
     ///
     /// This does *not* handle partial characters at the beginning or end of `input`!
@@ -288,41 +496,212 @@ pub trait Encoding {
                  trap: DecoderTrap,
                  ret: &mut StringWriter)
                  -> Result<(), Cow<'static, str>> {
-        // we don't need to keep `unprocessed` here;
-        // `raw_feed` should process as much input as possible.
-        let mut decoder = self.raw_decoder();
-        let mut remaining = 0;
-
-        loop {
-            let (offset, err) = decoder.raw_feed(&input[remaining..], ret);
-            let unprocessed = remaining + offset;
-            match err {
-                Some(err) => {
-                    remaining = (remaining as isize + err.upto) as usize;
-                    if !trap.trap(&mut *decoder, &input[unprocessed..remaining], ret) {
-                        return Err(err.cause);
-                    }
+        self.decode_to_with(input,
+                            &mut |decoder, input, output| trap.trap(decoder, input, output),
+                            ret)
+    }
+
+    /// Same as `decode` but calls the closure `trap` on the decoder error,
+    /// which may keep a state unlike `DecoderTrap::Call`.
+    /// The closure is given the current decoder, input and output writer,
+    /// and should return true only when it is fine to keep going.
+    fn decode_with(&self,
+                   input: &[u8],
+                   trap: &mut FnMut(&mut RawDecoder, &[u8], &mut StringWriter) -> bool)
+                   -> Result<String, Cow<'static, str>> {
+        let mut ret = String::new();
+        self.decode_to_with(input, trap, &mut ret).map(|_| ret)
+    }
+
+    /// Same as `decode_to` but calls the closure `trap` on the decoder error.
+    /// See `decode_with`.
+    fn decode_to_with(&self,
+                      input: &[u8],
+                      trap: &mut FnMut(&mut RawDecoder, &[u8], &mut StringWriter) -> bool,
+                      ret: &mut StringWriter)
+                      -> Result<(), Cow<'static, str>> {
+        decode_raw(&mut *self.raw_decoder(),
+                   input,
+                   &mut |decoder, _, input, output| trap(decoder, input, output),
+                   ret)
+    }
+
+    /// Same as `decode` but replaces every malformed sequence with `replacement`
+    /// instead of U+FFFD like `DecoderTrap::Replace` does.
+    fn decode_replacing(&self,
+                        input: &[u8],
+                        replacement: &str)
+                        -> Result<String, Cow<'static, str>> {
+        self.decode_with(input, &mut |_, _, output| {
+            output.write_str(replacement);
+            true
+        })
+    }
+}
+
+/// Encodes `input` with `encoder` as `Encoding::encode_to` does.
+/// `trap` is also given the byte offset of the problematic sequence in `input`.
+fn encode_raw(encoder: &mut RawEncoder,
+              input: &str,
+              trap: &mut FnMut(&mut RawEncoder, usize, &str, &mut ByteWriter) -> bool,
+              ret: &mut ByteWriter)
+              -> Result<(), Cow<'static, str>> {
+    // we don't need to keep `unprocessed` here;
+    // `raw_feed` should process as much input as possible.
+    let mut remaining = 0;
+
+    loop {
+        let (offset, err) = encoder.raw_feed(&input[remaining..], ret);
+        let unprocessed = remaining + offset;
+        match err {
+            Some(err) => {
+                remaining = (remaining as isize + err.upto) as usize;
+                if !trap(encoder, unprocessed, &input[unprocessed..remaining], ret) {
+                    return Err(err.cause);
                 }
-                None => {
-                    remaining = input.len();
-                    match decoder.raw_finish(ret) {
-                        Some(err) => {
-                            remaining = (remaining as isize + err.upto) as usize;
-                            if !trap.trap(&mut *decoder, &input[unprocessed..remaining], ret) {
-                                return Err(err.cause);
-                            }
+            }
+            None => {
+                remaining = input.len();
+                match encoder.raw_finish(ret) {
+                    Some(err) => {
+                        remaining = (remaining as isize + err.upto) as usize;
+                        if !trap(encoder, unprocessed, &input[unprocessed..remaining], ret) {
+                            return Err(err.cause);
                         }
-                        None => {}
                     }
-                    if remaining >= input.len() {
-                        return Ok(());
+                    None => {}
+                }
+                if remaining >= input.len() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Decodes `input` with `decoder` as `Encoding::decode_to` does.
+/// `trap` is also given the byte offset of the problematic sequence in `input`.
+fn decode_raw(decoder: &mut RawDecoder,
+              input: &[u8],
+              trap: &mut FnMut(&mut RawDecoder, usize, &[u8], &mut StringWriter) -> bool,
+              ret: &mut StringWriter)
+              -> Result<(), Cow<'static, str>> {
+    // we don't need to keep `unprocessed` here;
+    // `raw_feed` should process as much input as possible.
+    let mut remaining = 0;
+
+    loop {
+        let (offset, err) = decoder.raw_feed(&input[remaining..], ret);
+        let unprocessed = remaining + offset;
+        match err {
+            Some(err) => {
+                remaining = (remaining as isize + err.upto) as usize;
+                if !trap(decoder, unprocessed, &input[unprocessed..remaining], ret) {
+                    return Err(err.cause);
+                }
+            }
+            None => {
+                remaining = input.len();
+                match decoder.raw_finish(ret) {
+                    Some(err) => {
+                        remaining = (remaining as isize + err.upto) as usize;
+                        if !trap(decoder, unprocessed, &input[unprocessed..remaining], ret) {
+                            return Err(err.cause);
+                        }
                     }
+                    None => {}
                 }
+                if remaining >= input.len() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// A kind of `EncodingError`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncodingErrorKind {
+    /// The encoder input has a character that the encoding cannot represent.
+    Unmappable,
+    /// The decoder input has a malformed byte sequence.
+    Malformed,
+    /// The encoder input has a character that the encoding cannot represent,
+    /// and neither can it represent the replacement from the trap.
+    UnmappableReplacement,
+    /// The encoder input has a character too long to fit by itself in a limited
+    /// space, such as an encoded-word of `mime::encode_header`.
+    TooLong,
+}
+
+/// Error from `Encoding::encode_checked` or `Encoding::decode_checked`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodingError {
+    /// What is wrong with the input.
+    pub kind: EncodingErrorKind,
+    /// The byte offset of the problematic sequence in the input.
+    pub offset: usize,
+    /// The problematic sequence: the bytes for the decoder
+    /// or the UTF-8 representation of the characters for the encoder.
+    pub sequence: Vec<u8>,
+    /// The name of the encoding, as returned by `Encoding::name`.
+    pub encoding: &'static str,
+}
+
+impl EncodingError {
+    /// Returns the first unrepresentable character for `EncodingErrorKind::Unmappable`.
+    pub fn unmappable_char(&self) -> Option<char> {
+        match self.kind {
+            EncodingErrorKind::Unmappable |
+            EncodingErrorKind::UnmappableReplacement |
+            EncodingErrorKind::TooLong => {
+                ::std::str::from_utf8(&self.sequence).ok().and_then(|s| s.chars().next())
             }
+            EncodingErrorKind::Malformed => None,
         }
     }
 }
 
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            EncodingErrorKind::Unmappable => {
+                write!(f, "unrepresentable character")?;
+                if let Some(c) = self.unmappable_char() {
+                    write!(f, " U+{:04X}", c as u32)?;
+                }
+            }
+            EncodingErrorKind::UnmappableReplacement => {
+                write!(f, "unrepresentable replacement for character")?;
+                if let Some(c) = self.unmappable_char() {
+                    write!(f, " U+{:04X}", c as u32)?;
+                }
+            }
+            EncodingErrorKind::TooLong => {
+                write!(f, "too long character")?;
+                if let Some(c) = self.unmappable_char() {
+                    write!(f, " U+{:04X}", c as u32)?;
+                }
+            }
+            EncodingErrorKind::Malformed => {
+                write!(f, "invalid sequence")?;
+                for b in &self.sequence {
+                    write!(f, " {:02X}", b)?;
+                }
+            }
+        }
+        write!(f, " at byte {} in {}", self.offset, self.encoding)
+    }
+}
+
+impl error::Error for EncodingError {}
+
+impl From<EncodingError> for io::Error {
+    fn from(err: EncodingError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
 /// A type of the bare function in `EncoderTrap` values.
 pub type EncoderTrapFunc = fn(encoder: &mut RawEncoder,
                               input: &str,
@@ -402,39 +781,80 @@ pub enum EncoderTrap {
 
 impl EncoderTrap {
     /// Handles an encoder error. May write to the output writer.
-    /// Returns true only when it is fine to keep going.
+    /// Returns true only when it is fine to keep going,
+    /// which is not the case when the replacement cannot be represented in given encoding.
     pub fn trap(&self, encoder: &mut RawEncoder, input: &str, output: &mut ByteWriter) -> bool {
-        fn reencode(encoder: &mut RawEncoder,
-                    input: &str,
-                    output: &mut ByteWriter,
-                    trapname: &str)
-                    -> bool {
-            if encoder.is_ascii_compatible() {
-                // optimization!
-                output.write_bytes(input.as_bytes());
-            } else {
-                let (_, err) = encoder.raw_feed(input, output);
-                if err.is_some() {
-                    panic!("{} cannot reencode a replacement string", trapname);
-                }
-            }
-            true
-        }
+        self.try_trap(encoder, input, output).unwrap_or(false)
+    }
 
+    /// Same as `trap` but returns an error with the cause
+    /// when the replacement string cannot be represented in given encoding.
+    pub fn try_trap(&self,
+                    encoder: &mut RawEncoder,
+                    input: &str,
+                    output: &mut ByteWriter)
+                    -> Result<bool, Cow<'static, str>> {
         match *self {
-            EncoderTrap::Strict => false,
-            EncoderTrap::Replace => reencode(encoder, "?", output, "Replace"),
-            EncoderTrap::Ignore => true,
+            EncoderTrap::Strict => Ok(false),
+            EncoderTrap::Replace => reencode(encoder, "?", output),
+            EncoderTrap::Ignore => Ok(true),
             EncoderTrap::NcrEscape => {
                 let mut escapes = String::new();
                 for ch in input.chars() {
                     escapes.push_str(&format!("&#{};", ch as isize));
                 }
-                reencode(encoder, &escapes, output, "NcrEscape")
+                reencode(encoder, &escapes, output)
             }
-            EncoderTrap::Call(func) => func(encoder, input, output),
+            EncoderTrap::Call(func) => Ok(func(encoder, input, output)),
+        }
+    }
+}
+
+/// Writes the replacement string `input` to `output` through `encoder`.
+/// Fails when `encoder` cannot represent the replacement.
+fn reencode(encoder: &mut RawEncoder,
+            input: &str,
+            output: &mut ByteWriter)
+            -> Result<bool, Cow<'static, str>> {
+    if encoder.is_ascii_compatible() && input.is_ascii() {
+        // optimization!
+        output.write_bytes(input.as_bytes());
+    } else {
+        let (_, err) = encoder.raw_feed(input, output);
+        if err.is_some() {
+            return Err("cannot reencode a replacement string".into());
         }
     }
+    Ok(true)
+}
+
+/// Calls `f` with a closure applying `trap`, and makes the error from `f`
+/// tell that the replacement string could not be reencoded if that made `trap` fail.
+pub(crate) fn with_encoder_trap<T, F>(trap: EncoderTrap, f: F) -> Result<T, Cow<'static, str>>
+    where F: FnOnce(&mut FnMut(&mut RawEncoder, &str, &mut ByteWriter) -> bool)
+                    -> Result<T, Cow<'static, str>>
+{
+    with_fallible_trap(|encoder, input, output| trap.try_trap(encoder, input, output), f)
+}
+
+/// Same as `with_encoder_trap` but applies the closure `try_trap`,
+/// which returns the same as `EncoderTrap::try_trap`.
+fn with_fallible_trap<T, G, F>(mut try_trap: G, f: F) -> Result<T, Cow<'static, str>>
+    where G: FnMut(&mut RawEncoder, &str, &mut ByteWriter) -> Result<bool, Cow<'static, str>>,
+          F: FnOnce(&mut FnMut(&mut RawEncoder, &str, &mut ByteWriter) -> bool)
+                    -> Result<T, Cow<'static, str>>
+{
+    let mut cause = None;
+    let result = f(&mut |encoder, input, output| {
+        match try_trap(encoder, input, output) {
+            Ok(keep_going) => keep_going,
+            Err(err) => {
+                cause = Some(err);
+                false
+            }
+        }
+    });
+    result.map_err(|err| cause.unwrap_or(err))
 }
 
 impl Clone for EncoderTrap {
@@ -456,15 +876,149 @@ pub fn decode(input: &[u8],
               trap: DecoderTrap,
               fallback_encoding: EncodingRef)
               -> (Result<String, Cow<'static, str>>, EncodingRef) {
+    match sniff_bom(input) {
+        Some((encoding, len)) => (encoding.decode(&input[len..], trap), encoding),
+        None => (fallback_encoding.decode(input, trap), fallback_encoding),
+    }
+}
+
+/// Same as `decode` but borrows from `input` where `Encoding::decode_cow` does,
+/// e.g. when the rest of `input` after the BOM is valid UTF-8 and decoded as UTF-8.
+/// Return the result, the used encoding and whether the BOM was present.
+pub fn decode_cow<'a>(input: &'a [u8],
+                      trap: DecoderTrap,
+                      fallback_encoding: EncodingRef)
+                      -> (Result<Cow<'a, str>, Cow<'static, str>>, EncodingRef, bool) {
+    let (encoding, input, bom) = match sniff_bom(input) {
+        Some((encoding, len)) => (encoding, &input[len..], true),
+        None => (fallback_encoding, input, false),
+    };
+    (encoding.decode_cow(input, trap), encoding, bom)
+}
+
+/// Returns the encoding and the length of the Byte Order Mark (BOM)
+/// at the beginning of `input`, if any.
+fn sniff_bom(input: &[u8]) -> Option<(EncodingRef, usize)> {
     use all::{UTF_8, UTF_16LE, UTF_16BE};
     if input.starts_with(&[0xEF, 0xBB, 0xBF]) {
-        (UTF_8.decode(&input[3..], trap), UTF_8 as EncodingRef)
+        Some((UTF_8 as EncodingRef, 3))
     } else if input.starts_with(&[0xFE, 0xFF]) {
-        (UTF_16BE.decode(&input[2..], trap), UTF_16BE as EncodingRef)
+        Some((UTF_16BE as EncodingRef, 2))
     } else if input.starts_with(&[0xFF, 0xFE]) {
-        (UTF_16LE.decode(&input[2..], trap), UTF_16LE as EncodingRef)
+        Some((UTF_16LE as EncodingRef, 2))
     } else {
-        (fallback_encoding.decode(input, trap), fallback_encoding)
+        None
+    }
+}
+
+/// Returns true if `input` can be the beginning of a BOM but is too short to tell.
+fn is_partial_bom(input: &[u8]) -> bool {
+    match input {
+        [] | [0xEF] | [0xEF, 0xBB] | [0xFE] | [0xFF] => true,
+        _ => false,
+    }
+}
+
+/// Decoder which determines the encoding by looking for a Byte Order Mark (BOM)
+/// like `decode`, even when the BOM is split across `raw_feed` calls.
+/// The BOM is removed from the output.
+///
+/// The decoder buffers the input until it can tell whether the BOM is present,
+/// and keeps the determined encoding until it is dropped.
+pub struct BomSniffingDecoder {
+    fallback_encoding: EncodingRef,
+    /// The bytes fed so far while the BOM is undetermined.
+    pending: Vec<u8>,
+    /// The decoder for the determined encoding and whether the BOM was present.
+    decoder: Option<(Box<RawDecoder>, EncodingRef, bool)>,
+}
+
+impl BomSniffingDecoder {
+    /// Creates a new decoder which uses `fallback_encoding` when the BOM is absent.
+    pub fn new(fallback_encoding: EncodingRef) -> BomSniffingDecoder {
+        BomSniffingDecoder {
+            fallback_encoding: fallback_encoding,
+            pending: Vec::new(),
+            decoder: None,
+        }
+    }
+
+    /// Returns the used encoding, or `None` if it is not yet determined.
+    pub fn encoding(&self) -> Option<EncodingRef> {
+        self.decoder.as_ref().map(|&(_, encoding, _)| encoding)
+    }
+
+    /// Returns whether the BOM was present, or `None` if it is not yet determined.
+    pub fn has_bom(&self) -> Option<bool> {
+        self.decoder.as_ref().map(|&(_, _, bom)| bom)
+    }
+
+    /// Determines the encoding from the pending bytes and returns them without the BOM.
+    fn start(&mut self) -> Vec<u8> {
+        let mut pending = ::std::mem::replace(&mut self.pending, Vec::new());
+        let (encoding, bom) = match sniff_bom(&pending) {
+            Some((encoding, len)) => {
+                pending.drain(..len);
+                (encoding, true)
+            }
+            None => (self.fallback_encoding, false),
+        };
+        self.decoder = Some((encoding.raw_decoder(), encoding, bom));
+        pending
+    }
+}
+
+impl RawDecoder for BomSniffingDecoder {
+    fn from_self(&self) -> Box<RawDecoder> {
+        Box::new(BomSniffingDecoder::new(self.fallback_encoding))
+    }
+
+    fn is_ascii_compatible(&self) -> bool {
+        // a UTF-16 BOM may still follow.
+        match self.decoder {
+            Some((ref decoder, _, _)) => decoder.is_ascii_compatible(),
+            None => false,
+        }
+    }
+
+    fn raw_feed(&mut self, input: &[u8], output: &mut StringWriter) -> (usize, Option<CodecError>) {
+        if let Some((ref mut decoder, _, _)) = self.decoder {
+            return decoder.raw_feed(input, output);
+        }
+
+        self.pending.extend_from_slice(input);
+        if is_partial_bom(&self.pending) {
+            return (0, None);
+        }
+        let pending = self.start();
+        // `pending` ends with the current input, so it starts `shift` bytes after
+        // the beginning of the current input (or before it if `shift` is negative).
+        let shift = input.len() as isize - pending.len() as isize;
+        let decoder = &mut self.decoder.as_mut().unwrap().0;
+        let (offset, err) = decoder.raw_feed(&pending, output);
+        let offset = offset as isize + shift;
+        (if offset > 0 { offset as usize } else { 0 },
+         err.map(|err| {
+            CodecError {
+                upto: err.upto + shift,
+                cause: err.cause,
+            }
+        }))
+    }
+
+    fn raw_finish(&mut self, output: &mut StringWriter) -> Option<CodecError> {
+        if self.decoder.is_none() {
+            // the input is too short to contain the BOM.
+            let pending = self.start();
+            let decoder = &mut self.decoder.as_mut().unwrap().0;
+            if let (_, Some(err)) = decoder.raw_feed(&pending, output) {
+                return Some(CodecError {
+                    upto: err.upto - pending.len() as isize,
+                    cause: err.cause,
+                });
+            }
+        }
+        self.decoder.as_mut().unwrap().0.raw_finish(output)
     }
 }
 
@@ -473,6 +1027,7 @@ mod tests {
     use super::*;
     use super::EncoderTrap::NcrEscape;
     use util::StrCharIndex;
+    use std::borrow::Cow;
     use std::convert::Into;
 
     // a contrived encoding example: same as ASCII, but inserts `prepend` between each character
@@ -586,7 +1141,150 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
+    fn test_encode_cow() {
+        static COMPAT: &'static MyEncoding = &MyEncoding {
+            flag: true,
+            prohibit: '\u{80}',
+            prepend: "",
+        };
+        static INCOMPAT: &'static MyEncoding = &MyEncoding {
+            flag: false,
+            prohibit: '\u{80}',
+            prepend: "",
+        };
+
+        assert!(COMPAT.is_ascii_compatible());
+        assert!(!INCOMPAT.is_ascii_compatible());
+        match COMPAT.encode_cow("Hello", NcrEscape) {
+            Ok(Cow::Borrowed(bytes)) => assert_eq!(bytes, b"Hello"),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match COMPAT.encode_cow("Hello\u{203d}", NcrEscape) {
+            Ok(Cow::Owned(bytes)) => assert_eq!(bytes, b"Hello&#8253;"),
+            result => panic!("unexpected result {:?}", result),
+        }
+        match INCOMPAT.encode_cow("Hello", NcrEscape) {
+            Ok(Cow::Owned(bytes)) => assert_eq!(bytes, b"Hello"),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_encode_with_stateful_trap() {
+        static COMPAT: &'static MyEncoding = &MyEncoding {
+            flag: true,
+            prohibit: '\u{80}',
+            prepend: "",
+        };
+
+        let mut count = 0;
+        assert_eq!(COMPAT.encode_with("Hello\u{203d} I'm fine\u{2026}", &mut |_, _, output| {
+                             count += 1;
+                             output.write_bytes(format!("<{}>", count).as_bytes());
+                             true
+                         }),
+                   Ok(b"Hello<1> I'm fine<2>".to_vec()));
+        assert_eq!(count, 2);
+
+        assert!(COMPAT.encode_with("\u{203d}", &mut |_, _, _| false).is_err());
+    }
+
+    #[test]
+    fn test_encode_checked() {
+        static COMPAT: &'static MyEncoding = &MyEncoding {
+            flag: true,
+            prohibit: '\u{80}',
+            prepend: "",
+        };
+
+        assert_eq!(COMPAT.encode_checked("Hello", EncoderTrap::Strict), Ok(b"Hello".to_vec()));
+        let err = COMPAT.encode_checked("Hello\u{203d} I'm fine.", EncoderTrap::Strict)
+                        .unwrap_err();
+        assert_eq!(err,
+                   EncodingError {
+                       kind: EncodingErrorKind::Unmappable,
+                       offset: 5,
+                       sequence: "\u{203d}".as_bytes().to_vec(),
+                       encoding: "my encoding",
+                   });
+        assert_eq!(err.unmappable_char(), Some('\u{203d}'));
+        assert_eq!(err.to_string(), "unrepresentable character U+203D at byte 5 in my encoding");
+        let err: ::std::io::Error = err.into();
+        assert_eq!(err.kind(), ::std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_encode_replacing() {
+        static COMPAT: &'static MyEncoding = &MyEncoding {
+            flag: true,
+            prohibit: '\u{80}',
+            prepend: "",
+        };
+
+        assert_eq!(COMPAT.encode_replacing("Hello\u{203d}", "\u{1a}"), Ok(b"Hello\x1a".to_vec()));
+        assert_eq!(COMPAT.encode_replacing("Hello\u{203d}", ""), Ok(b"Hello".to_vec()));
+        assert_eq!(COMPAT.encode_replacing("Hello\u{203d}", "\u{3013}"),
+                   Err("cannot reencode a replacement string".into()));
+        let mut ret = Vec::new();
+        assert_eq!(COMPAT.encode_to_replacing("Hello\u{203d}", "\u{3013}", &mut ret),
+                   Err("cannot reencode a replacement string".into()));
+    }
+
+    #[test]
+    fn test_first_unmappable() {
+        static COMPAT: &'static MyEncoding = &MyEncoding {
+            flag: true,
+            prohibit: '\u{80}',
+            prepend: "",
+        };
+
+        assert_eq!(COMPAT.first_unmappable("Hello"), None);
+        assert!(COMPAT.can_encode("Hello"));
+        assert_eq!(COMPAT.first_unmappable("Hello\u{203d}\u{e9}"), Some((5, '\u{203d}')));
+        assert!(!COMPAT.can_encode("Hello\u{203d}"));
+    }
+
+    #[test]
+    fn test_first_unmappable_on_finish() {
+        // an encoder that fails only on finishing.
+        struct UnfinishedEncoder;
+        impl RawEncoder for UnfinishedEncoder {
+            fn from_self(&self) -> Box<RawEncoder> {
+                Box::new(UnfinishedEncoder)
+            }
+            fn raw_feed(&mut self,
+                        input: &str,
+                        output: &mut ByteWriter)
+                        -> (usize, Option<CodecError>) {
+                output.write_bytes(input.as_bytes());
+                (input.len(), None)
+            }
+            fn raw_finish(&mut self, _output: &mut ByteWriter) -> Option<CodecError> {
+                Some(CodecError {
+                    upto: 0,
+                    cause: "unfinished".into(),
+                })
+            }
+        }
+        struct Unfinished;
+        impl Encoding for Unfinished {
+            fn name(&self) -> &'static str {
+                "unfinished"
+            }
+            fn raw_encoder(&self) -> Box<RawEncoder> {
+                Box::new(UnfinishedEncoder)
+            }
+            fn raw_decoder(&self) -> Box<RawDecoder> {
+                panic!("not supported")
+            }
+        }
+
+        assert_eq!(Unfinished.encode_checked("Hello", EncoderTrap::Strict).unwrap_err().offset, 5);
+        assert_eq!(Unfinished.first_unmappable("Hello"), None);
+        assert!(!Unfinished.can_encode("Hello"));
+    }
+
+    #[test]
     fn test_reencoding_trap_can_fail() {
         static FAIL: &'static MyEncoding = &MyEncoding {
             flag: false,
@@ -595,6 +1293,13 @@ mod tests {
         };
 
         // this should fail as this contrived encoding does not support `&` at all
-        let _ = FAIL.encode("Hello\u{203d} I'm fine.", NcrEscape);
+        assert_eq!(FAIL.encode("Hello\u{203d} I'm fine.", NcrEscape),
+                   Err("cannot reencode a replacement string".into()));
+        let mut ret = Vec::new();
+        assert_eq!(FAIL.encode_to("Hello\u{203d} I'm fine.", NcrEscape, &mut ret),
+                   Err("cannot reencode a replacement string".into()));
+        let err = FAIL.encode_checked("Hello\u{203d} I'm fine.", NcrEscape).unwrap_err();
+        assert_eq!((err.kind, err.offset), (EncodingErrorKind::UnmappableReplacement, 5));
+        assert!(!NcrEscape.trap(&mut *FAIL.raw_encoder(), "\u{203d}", &mut ret));
     }
 }